@@ -0,0 +1,180 @@
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use filetransfer_core::common::paths;
+use filetransfer_core::common::transfer_state::format_bytes;
+use filetransfer_core::daemon::{self, client::{self, DaemonClient, DaemonError}, server};
+use filetransfer_core::engine::events::Event;
+
+use crate::events::{EventLog, log_event};
+use crate::{EXIT_FAILED, EXIT_NOT_FOUND, Options, pin_receive_dir, event_log, follow_send, server_ports, start_node, usage_error};
+
+// A receiver with no window, the other commands and the GUI attach to its control socket
+pub fn run(options: &Options) -> ExitCode {
+    let path = paths::daemon_socket();
+    // Checked again by server::serve, but before binding any port
+    if DaemonClient::connect(&path).is_ok() {
+        eprintln!("A daemon is already running on {}", path.display());
+        return ExitCode::from(EXIT_FAILED);
+    }
+    let log = match event_log(options) {
+        Ok(log) => log,
+        Err(code) => return code,
+    };
+    if let Err(code) = pin_receive_dir(options) {
+        return code;
+    }
+
+    let node = Arc::new(start_node(server_ports(options)));
+
+    if let Some(log) = log {
+        let events = node.subscribe_events();
+        thread::spawn(move || {
+            let mut log = Some(log);
+            for event in events {
+                log_event(&mut log, &event);
+            }
+        });
+    }
+
+    if options.auto_accept {
+        thread::spawn({
+            let node = node.clone();
+            move || {
+                for event in node.subscribe_events() {
+                    if let Event::RequestReceived { id, request } = event {
+                        node.accept_request(id, request.files.iter().map(|f| f.name.clone()).collect());
+                    }
+                }
+            }
+        });
+    }
+
+    match server::serve(node, &path) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Cannot start the daemon: {}", e);
+            ExitCode::from(EXIT_FAILED)
+        }
+    }
+}
+
+fn connect() -> Result<DaemonClient, ExitCode> {
+    let path = paths::daemon_socket();
+    DaemonClient::connect(&path).map_err(|e| {
+        eprintln!("No daemon on {}: {}", path.display(), e);
+        ExitCode::from(EXIT_FAILED)
+    })
+}
+
+fn failed(action: &str, error: DaemonError) -> ExitCode {
+    eprintln!("Cannot {}: {}", action, error);
+    match error {
+        DaemonError::Rpc(e) if e.code == daemon::UNKNOWN_REQUEST || e.code == daemon::PEER_NOT_FOUND => ExitCode::from(EXIT_NOT_FOUND),
+        _ => ExitCode::from(EXIT_FAILED),
+    }
+}
+
+// One line per request, then its files indented
+pub fn requests() -> ExitCode {
+    let client = match connect() {
+        Ok(client) => client,
+        Err(code) => return code,
+    };
+
+    let requests = match client.requests() {
+        Ok(requests) => requests,
+        Err(e) => return failed("list the requests", e),
+    };
+    for pending in requests.iter() {
+        let request = &pending.request;
+        let size: u64 = request.files.iter().map(|f| f.size).sum();
+        println!("{}\t{} ({})\t{} file(s)\t{}", pending.id, request.from.name(), request.from.addr, request.files.len(), format_bytes(size));
+        for file in request.files.iter() {
+            println!("\t{}\t{}", file.name, format_bytes(file.size));
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+// `ft accept <id> [names...]` takes every file without names
+pub fn answer(options: &Options, accept: bool) -> ExitCode {
+    let Ok(id) = options.positional[0].parse::<u32>() else {
+        return usage_error(&format!("Invalid request id {}", options.positional[0]));
+    };
+    let client = match connect() {
+        Ok(client) => client,
+        Err(code) => return code,
+    };
+
+    let names = options.positional[1..].to_vec();
+    let result = if !accept {
+        client.reject_request(id)
+    } else if names.is_empty() {
+        client.accept_request(id, None)
+    } else {
+        client.accept_request(id, Some(names))
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => failed(if accept { "accept the request" } else { "reject the request" }, e),
+    }
+}
+
+// Like a local send, with the daemon's node and its already discovered peers
+pub fn send(options: &Options, client: &DaemonClient, files: Vec<String>, log: Option<EventLog>) -> ExitCode {
+    let query = &options.positional[0];
+
+    // The daemon runs in another directory
+    let files = match files.iter().map(|f| std::path::absolute(f).map(|p| p.to_string_lossy().into_owned())).collect::<Result<Vec<_>, _>>() {
+        Ok(files) => files,
+        Err(e) => return usage_error(&format!("Cannot resolve the paths: {}", e)),
+    };
+
+    let events = match client::subscribe(&paths::daemon_socket()) {
+        Ok(events) => events,
+        Err(e) => return failed("follow the transfers", e),
+    };
+
+    let since = SystemTime::now();
+    let deadline = Instant::now() + options.wait;
+    loop {
+        match client.send_files(query, files.clone()) {
+            Ok(()) => break,
+            Err(DaemonError::Rpc(e)) if e.code == daemon::PEER_NOT_FOUND && Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(250));
+            }
+            Err(e) => return failed("send", e),
+        }
+    }
+
+    eprintln!("Sending {} file(s) to {} through the daemon, waiting for them to accept", files.len(), query);
+    follow_send(events, &files, since, log)
+}
+
+// Everything the daemon does, to stdout or appended to --events FILE, until it exits
+pub fn events(options: &Options) -> ExitCode {
+    let mut log = match event_log(options) {
+        Ok(log) => log.unwrap_or_else(EventLog::stdout),
+        Err(code) => return code,
+    };
+    let events = match client::subscribe(&paths::daemon_socket()) {
+        Ok(events) => events,
+        Err(e) => {
+            eprintln!("No daemon on {}: {}", paths::daemon_socket().display(), e);
+            return ExitCode::from(EXIT_FAILED);
+        }
+    };
+
+    for event in events {
+        // The reader went away, e.g. `ft events | head`
+        if log.write(&event).is_err() {
+            return ExitCode::SUCCESS;
+        }
+    }
+    eprintln!("The daemon exited");
+    ExitCode::from(EXIT_FAILED)
+}
@@ -0,0 +1,44 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+
+use filetransfer_core::engine::events::Event;
+use filetransfer_core::engine::json::JsonEvents;
+
+// The events as NDJSON, see engine::json for the records
+pub struct EventLog {
+    records: JsonEvents,
+    out: Box<dyn Write + Send>,
+}
+
+impl EventLog {
+    // Appended to, several runs can share the file
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(EventLog { records: JsonEvents::new(), out: Box::new(file) })
+    }
+
+    pub fn stdout() -> Self {
+        EventLog { records: JsonEvents::new(), out: Box::new(io::stdout()) }
+    }
+
+    // One line per record, written at once so readers never see half of one
+    pub fn write(&mut self, event: &Event) -> io::Result<()> {
+        for record in self.records.records(event) {
+            let mut line = serde_json::to_string(&record)?;
+            line.push('\n');
+            self.out.write_all(line.as_bytes())?;
+        }
+        self.out.flush()
+    }
+}
+
+// Stops logging after the first error instead of failing the transfers
+pub fn log_event(log: &mut Option<EventLog>, event: &Event) {
+    if let Some(writer) = log
+        && let Err(e) = writer.write(event)
+    {
+        eprintln!("Cannot write the events: {}", e);
+        *log = None;
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashSet;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use filetransfer_core::Node;
+use filetransfer_core::common::{args, config, identity, notify, paths};
+use filetransfer_core::common::identity::Identity;
+use filetransfer_core::common::args::PortOptions;
+use filetransfer_core::common::transfer_state::{BatchProgress, TransferState, format_bytes};
+use filetransfer_core::engine::{self, events::Event};
+use filetransfer_core::networking::client::PingResponse;
+use filetransfer_core::networking::ports::{self, Ports};
+use filetransfer_core::networking::server::RequestData;
+
+#[cfg(unix)]
+mod daemon;
+mod events;
+mod progress;
+
+use events::{EventLog, log_event};
+use progress::ProgressLine;
+#[cfg(unix)]
+use filetransfer_core::daemon::NodeStatus;
+#[cfg(unix)]
+use filetransfer_core::networking::supervisor::ServiceState;
+#[cfg(unix)]
+use filetransfer_core::daemon::client::DaemonClient;
+
+const USAGE: &str = "Usage:
+  ft peers [--wait SECS]
+  ft send <peer> <paths...> [--wait SECS] [--events FILE]
+  ft receive [--dir DIR] [--auto-accept] [--once] [--events FILE]
+  ft status
+  ft daemon [--dir DIR] [--auto-accept] [--events FILE]
+  ft requests
+  ft accept <id> [names...]
+  ft reject <id>
+  ft events [--events FILE]
+
+<peer> is a name, a device ID or an address. peers and send go through the daemon when it runs.
+--events FILE appends every event to FILE as JSON, one per line. ft events prints those of the daemon.
+Shared options:";
+
+/*
+0  everything was sent or received
+1  a transfer failed or was rejected, or no peer answered
+2  bad arguments
+3  the peer or the request was not found
+*/
+const EXIT_FAILED: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_NOT_FOUND: u8 = 3;
+
+// Discovery runs every few seconds, give it one round before giving up
+const DEFAULT_WAIT: Duration = Duration::from_secs(5);
+const REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+struct Options {
+    command: String,
+    positional: Vec<String>,
+    ports: PortOptions,
+    wait: Duration,
+    dir: Option<PathBuf>,
+    auto_accept: bool,
+    once: bool,
+    events: Option<PathBuf>,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut ports = PortOptions::default();
+    let mut positional = Vec::new();
+    let mut wait = DEFAULT_WAIT;
+    let mut dir = None;
+    let mut auto_accept = false;
+    let mut once = false;
+    let mut events = None;
+    let mut args = args.iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        if args::parse_option(arg, &mut args, &mut ports)? {
+            continue;
+        }
+
+        match arg.as_str() {
+            "--wait" => {
+                let value = args.next().ok_or("Missing value for --wait")?;
+                let secs: u64 = value.parse().map_err(|_| format!("Invalid number of seconds: {}", value))?;
+                wait = Duration::from_secs(secs);
+            }
+            "--dir" => dir = Some(PathBuf::from(args.next().ok_or("Missing value for --dir")?)),
+            "--auto-accept" => auto_accept = true,
+            "--once" => once = true,
+            "--events" => events = Some(PathBuf::from(args.next().ok_or("Missing value for --events")?)),
+            _ if arg.starts_with("--") => return Err(format!("Unknown argument {}", arg)),
+            _ => positional.push(arg.clone()),
+        }
+    }
+
+    if positional.is_empty() {
+        return Err(String::from("Missing command"));
+    }
+    let command = positional.remove(0);
+
+    Ok(Options { command, positional, ports, wait, dir, auto_accept, once, events })
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => return usage_error(&e),
+    };
+
+    match options.command.as_str() {
+        "peers" if options.positional.is_empty() => peers(&options),
+        "send" if options.positional.len() >= 2 => send(&options),
+        "receive" if options.positional.is_empty() => receive(&options),
+        "status" if options.positional.is_empty() => status(&options),
+        #[cfg(unix)]
+        "daemon" if options.positional.is_empty() => daemon::run(&options),
+        #[cfg(unix)]
+        "requests" if options.positional.is_empty() => daemon::requests(),
+        #[cfg(unix)]
+        "accept" if !options.positional.is_empty() => daemon::answer(&options, true),
+        #[cfg(unix)]
+        "reject" if options.positional.len() == 1 => daemon::answer(&options, false),
+        #[cfg(unix)]
+        "events" if options.positional.is_empty() => daemon::events(&options),
+        "peers" | "send" | "receive" | "status" | "daemon" | "requests" | "accept" | "reject" | "events" => {
+            usage_error(&format!("Wrong arguments for {}", options.command))
+        }
+        command => usage_error(&format!("Unknown command {}", command)),
+    }
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("{}\n{}\n  {}", message, USAGE, args::OPTIONS_USAGE);
+    ExitCode::from(EXIT_USAGE)
+}
+
+// Opened before anything starts, so a bad path is a usage error
+fn event_log(options: &Options) -> Result<Option<EventLog>, ExitCode> {
+    let Some(path) = &options.events else {
+        return Ok(None);
+    };
+    match EventLog::open(path) {
+        Ok(log) => Ok(Some(log)),
+        Err(e) => Err(usage_error(&format!("Cannot open {}: {}", path.display(), e))),
+    }
+}
+
+// Joined and left notifications are only shown by the GUI
+fn start_node(ports: Ports) -> Node {
+    let (presence_events, _) = mpsc::channel();
+    Node::start(ports, notify::none(), presence_events)
+}
+
+// Nobody would answer the requests of a node that only lists peers or sends, it doesn't accept any
+fn start_sender(ports: Ports) -> Node {
+    let (presence_events, _) = mpsc::channel();
+    Node::start_sender(ports, notify::none(), presence_events)
+}
+
+// Those of the config file, with the command line on top
+fn server_ports(options: &Options) -> Ports {
+    options.ports.apply(config::current().ports)
+}
+
+// Senders don't need the well known ports, peers connect to the advertised ones.
+// This way they also run next to the GUI or a receiver on the same machine
+fn client_ports(options: &Options) -> Ports {
+    let ports = server_ports(options);
+    if options.ports.is_empty() {
+        Ports { info: ports.info, ..Ports::ephemeral() }
+    } else {
+        ports
+    }
+}
+
+fn peers(options: &Options) -> ExitCode {
+    // Its discovery has been running for a while, no need to wait
+    #[cfg(unix)]
+    if let Ok(client) = DaemonClient::connect(&paths::daemon_socket()) {
+        return match client.peers() {
+            Ok(peers) => print_peers(&peers),
+            Err(e) => {
+                eprintln!("Cannot list the peers: {}", e);
+                ExitCode::from(EXIT_FAILED)
+            }
+        };
+    }
+
+    let (presence_events, _) = mpsc::channel();
+    let node = Node::start_discovery(client_ports(options).info, notify::none(), presence_events);
+    thread::sleep(options.wait);
+    print_peers(&node.peers())
+}
+
+fn print_peers(peers: &[PingResponse]) -> ExitCode {
+    for peer in peers.iter() {
+        let id = if peer.device_id.is_empty() { "-" } else { &peer.device_id };
+        println!("{}\t{}\t{}\t{}", peer.name(), peer.addr, peer.os, id);
+    }
+
+    if peers.is_empty() {
+        eprintln!("No peers found");
+        return ExitCode::from(EXIT_FAILED);
+    }
+    ExitCode::SUCCESS
+}
+
+fn send(options: &Options) -> ExitCode {
+    let query = &options.positional[0];
+    let files = options.positional[1..].to_vec();
+
+    // Directories are not supported by the protocol, catch them before asking the receiver
+    for file in files.iter() {
+        match std::fs::symlink_metadata(file) {
+            Ok(metadata) if metadata.is_dir() => return usage_error(&format!("{} is a directory", file)),
+            Ok(_) => (),
+            Err(e) => return usage_error(&format!("Cannot read {}: {}", file, e)),
+        }
+    }
+
+    let log = match event_log(options) {
+        Ok(log) => log,
+        Err(code) => return code,
+    };
+
+    #[cfg(unix)]
+    if let Ok(client) = DaemonClient::connect(&paths::daemon_socket()) {
+        return daemon::send(options, &client, files, log);
+    }
+
+    let node = start_sender(client_ports(options));
+
+    let deadline = Instant::now() + options.wait;
+    let peer = loop {
+        if let Some(peer) = node.find_peer(query) {
+            break peer;
+        }
+        if Instant::now() >= deadline {
+            eprintln!("Peer {} not found", query);
+            return ExitCode::from(EXIT_NOT_FOUND);
+        }
+        thread::sleep(Duration::from_millis(250));
+    };
+
+    eprintln!("Sending {} file(s) to {} ({}), waiting for them to accept", files.len(), peer.name(), peer.addr);
+
+    let since = SystemTime::now();
+    let events = node.subscribe_events();
+    node.send_files(peer, files.clone());
+    follow_send(events, &files, since, log)
+}
+
+// Until every file is sent or failed. There is one transfer per file whatever happens to the request,
+// older ones for the same files are left out by their start time
+fn follow_send(events: Receiver<Event>, files: &[String], since: SystemTime, mut log: Option<EventLog>) -> ExitCode {
+    let ours: HashSet<&String> = files.iter().collect();
+    let is_ours = |t: &&TransferState| ours.contains(&t.original_filepath) && t.started_at >= since;
+    let mut state = engine::State::default();
+    let mut progress = ProgressLine::new();
+
+    loop {
+        match events.recv_timeout(REDRAW_INTERVAL) {
+            Ok(event) => {
+                log_event(&mut log, &event);
+                state.apply(&event);
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        while let Ok(event) = events.try_recv() {
+            log_event(&mut log, &event);
+            state.apply(&event);
+        }
+
+        let mut total = BatchProgress::default();
+        for transfer in state.transfers.values().filter(is_ours) {
+            total.add(transfer);
+        }
+        if total.transfers > 0 {
+            progress.draw(&total);
+        }
+
+        if total.transfers >= files.len() && total.finished == total.transfers {
+            break;
+        }
+    }
+    progress.clear();
+
+    let mut transfers: Vec<&TransferState> = state.transfers.values().filter(is_ours).collect();
+    transfers.sort_by(|a, b| a.original_filepath.cmp(&b.original_filepath));
+    report(&transfers)
+}
+
+// One line per transfer and a summary, the exit code says if anything failed
+fn report(transfers: &[&TransferState]) -> ExitCode {
+    let mut failed = 0;
+    let mut bytes = 0;
+    for transfer in transfers.iter() {
+        match transfer.error_text() {
+            Some(reason) => {
+                failed += 1;
+                println!("failed {}: {}", transfer.original_filepath, reason);
+            }
+            None => {
+                bytes += transfer.bytes_total;
+                println!("sent {} ({})", transfer.original_filepath, format_bytes(transfer.bytes_total));
+            }
+        }
+    }
+
+    eprintln!("{} sent, {} failed, {} in total", transfers.len() - failed, failed, format_bytes(bytes));
+    if failed > 0 { ExitCode::from(EXIT_FAILED) } else { ExitCode::SUCCESS }
+}
+
+// --dir wins over the config file, see config::receive_dir
+fn pin_receive_dir(options: &Options) -> Result<(), ExitCode> {
+    let Some(dir) = &options.dir else {
+        return Ok(());
+    };
+    if !dir.is_dir() {
+        eprintln!("Cannot use {} as the receive directory: not a directory", dir.display());
+        return Err(ExitCode::from(EXIT_USAGE));
+    }
+    config::pin_receive_dir(std::path::absolute(dir).unwrap_or_else(|_| dir.clone()));
+    Ok(())
+}
+
+fn receive(options: &Options) -> ExitCode {
+    let mut log = match event_log(options) {
+        Ok(log) => log,
+        Err(code) => return code,
+    };
+    if let Err(code) = pin_receive_dir(options) {
+        return code;
+    }
+
+    let node = start_node(server_ports(options));
+    let events = node.subscribe_events();
+    eprintln!("Waiting for requests as {} on port {}", node.identity.lock().unwrap().display_name, node.ports.control);
+
+    let mut state = engine::State::default();
+    let mut progress = ProgressLine::new();
+    // With --once, the request we wait for and how many files it has
+    let mut first: Option<(u32, usize)> = None;
+    let mut failed = false;
+
+    loop {
+        let event = match events.recv_timeout(REDRAW_INTERVAL) {
+            Ok(event) => event,
+            Err(RecvTimeoutError::Timeout) => {
+                draw_receiving(&state, &mut progress);
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => return ExitCode::from(EXIT_FAILED),
+        };
+        log_event(&mut log, &event);
+        state.apply(&event);
+
+        match &event {
+            Event::RequestReceived { id, request } => {
+                progress.clear();
+                let accepted = answer(request, options.auto_accept);
+                if options.once && first.is_none() && !accepted.is_empty() {
+                    first = Some((*id, accepted.len()));
+                }
+                node.accept_request(*id, accepted);
+            }
+            Event::TransferAdded { id, .. } | Event::TransferChanged { id, .. } => {
+                if let Some(transfer) = state.transfers.get(id).filter(|t| t.ttype.is_finished()) {
+                    match transfer.error_text() {
+                        Some(reason) => {
+                            failed = true;
+                            progress.println(&format!("failed {}: {}", transfer.dest_filepath, reason));
+                        }
+                        None => progress.println(&format!("received {} ({})", transfer.dest_filepath, format_bytes(transfer.bytes_total))),
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        // Local copies and symlinks are part of the request too, see server::control_connection
+        if let Some((id, count)) = first {
+            let batch: Vec<&TransferState> = state.transfers.values().filter(|t| t.batch == Some(id)).collect();
+            if batch.len() >= count && batch.iter().all(|t| t.ttype.is_finished()) {
+                progress.clear();
+                return if failed { ExitCode::from(EXIT_FAILED) } else { ExitCode::SUCCESS };
+            }
+        }
+
+        draw_receiving(&state, &mut progress);
+    }
+}
+
+fn draw_receiving(state: &engine::State, progress: &mut ProgressLine) {
+    let mut total = BatchProgress::default();
+    for transfer in state.transfers.values().filter(|t| !t.ttype.is_finished()) {
+        total.add(transfer);
+    }
+    if total.transfers > 0 {
+        progress.draw(&total);
+    }
+}
+
+// The names of the accepted files, an empty list rejects the request
+fn answer(request: &RequestData, auto_accept: bool) -> Vec<String> {
+    let size: u64 = request.files.iter().map(|f| f.size).sum();
+    eprintln!("Request from {} ({}): {} file(s), {}", request.from.name(), request.from.addr, request.files.len(), format_bytes(size));
+    for file in request.files.iter() {
+        eprintln!("  {} ({})", file.name, format_bytes(file.size));
+    }
+
+    let names = request.files.iter().map(|f| f.name.clone()).collect();
+    if auto_accept {
+        return names;
+    }
+
+    eprint!("Accept? [y/N] ");
+    let _ = io::stderr().flush();
+    let mut line = String::new();
+    // No terminal to answer from counts as a no
+    let _ = io::stdin().lock().read_line(&mut line);
+    if line.trim().eq_ignore_ascii_case("y") || line.trim().eq_ignore_ascii_case("yes") {
+        names
+    } else {
+        eprintln!("Rejected");
+        Vec::new()
+    }
+}
+
+// The daemon's node when one runs. Otherwise whether a receiver could start here, without starting one:
+// the ports are only bound for a moment, nothing is announced and no ping is answered
+fn status(options: &Options) -> ExitCode {
+    println!("config dir\t{}", paths::config_dir().display());
+    for problem in config::load().1 {
+        println!("config file\tignored {}", problem);
+    }
+
+    #[cfg(unix)]
+    if let Ok(client) = DaemonClient::connect(&paths::daemon_socket()) {
+        return match client.status() {
+            Ok(status) => print_daemon_status(&status),
+            Err(e) => {
+                eprintln!("Cannot get the daemon status: {}", e);
+                ExitCode::from(EXIT_FAILED)
+            }
+        };
+    }
+
+    let identity = Identity::load_or_create();
+    let name = config::current().display_name
+        .map(|name| identity::sanitize_display_name(&name))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(identity.display_name);
+    println!("name\t{}", name);
+    println!("device id\t{}", identity.device_id);
+    println!("daemon\tnot running");
+    println!("receive dir\t{}", config::receive_dir(&config::current()).display());
+
+    // The info port is shared by every instance, it is always free
+    let ports = server_ports(options);
+    let mut in_use = false;
+    for (name, port) in [("control", ports.control), ("data", ports.data)] {
+        match ports::bind_tcp(port) {
+            Ok(_) => println!("{} port\t{} free", name, port),
+            Err(e) => {
+                in_use = true;
+                println!("{} port\t{} unavailable, {}", name, port, e);
+            }
+        }
+    }
+
+    if in_use { ExitCode::from(EXIT_FAILED) } else { ExitCode::SUCCESS }
+}
+
+#[cfg(unix)]
+fn print_daemon_status(status: &NodeStatus) -> ExitCode {
+    println!("name\t{}", status.name);
+    println!("device id\t{}", status.device_id);
+    println!("daemon\trunning on {}", paths::daemon_socket().display());
+    println!("receive dir\t{}", status.receive_dir.display());
+    println!("ports\tcontrol {}, data {}, info {}, discovery {}", status.ports.control, status.ports.data, status.ports.info, status.ports.discovery);
+    println!("mdns\t{}", if status.mdns { "running" } else { "disabled" });
+
+    let mut offline = false;
+    for (name, state) in status.services.iter() {
+        offline |= *state != ServiceState::Running;
+        println!("{}\t{}", name, state);
+    }
+    println!("peers\t{}", status.peers);
+
+    if offline { ExitCode::from(EXIT_FAILED) } else { ExitCode::SUCCESS }
+}
+
@@ -0,0 +1,65 @@
+use std::io::{self, IsTerminal, Write};
+
+use filetransfer_core::common::rate;
+use filetransfer_core::common::transfer_state::{BatchProgress, format_bytes};
+
+const BAR_WIDTH: usize = 30;
+
+// A single line on stderr redrawn in place, only when stderr is a terminal so scripts get clean logs
+pub struct ProgressLine {
+    enabled: bool,
+    drawn: usize,
+}
+
+impl ProgressLine {
+    pub fn new() -> Self {
+        ProgressLine {
+            enabled: io::stderr().is_terminal(),
+            drawn: 0,
+        }
+    }
+
+    // E.g. "[##########--------------------]  33%  1.2 GB / 3.6 GB  45.0 MB/s  ETA 53s"
+    pub fn draw(&mut self, progress: &BatchProgress) {
+        if !self.enabled {
+            return;
+        }
+
+        let fraction = if progress.bytes_total == 0 {
+            progress.finished as f64 / progress.transfers.max(1) as f64
+        } else {
+            progress.bytes_done as f64 / progress.bytes_total as f64
+        };
+        let filled = ((fraction * BAR_WIDTH as f64) as usize).min(BAR_WIDTH);
+
+        let mut line = format!("[{}{}] {:>3}%  {} / {}",
+            "#".repeat(filled), "-".repeat(BAR_WIDTH - filled), (fraction * 100.0) as u32,
+            format_bytes(progress.bytes_done), format_bytes(progress.bytes_total));
+        if progress.bytes_per_sec > 0.0 {
+            line.push_str(&format!("  {}", rate::format_rate(progress.bytes_per_sec)));
+        }
+        if let Some(eta) = progress.eta() {
+            line.push_str(&format!("  ETA {}", rate::format_duration(eta)));
+        }
+
+        // Pad over the end of a longer previous line
+        let width = line.chars().count();
+        eprint!("\r{}{}", line, " ".repeat(self.drawn.saturating_sub(width)));
+        let _ = io::stderr().flush();
+        self.drawn = width;
+    }
+
+    pub fn clear(&mut self) {
+        if self.drawn > 0 {
+            eprint!("\r{}\r", " ".repeat(self.drawn));
+            let _ = io::stderr().flush();
+            self.drawn = 0;
+        }
+    }
+
+    // Printed above the bar, the next draw puts it back
+    pub fn println(&mut self, line: &str) {
+        self.clear();
+        println!("{}", line);
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+
+use crate::common::paths;
+use crate::networking::ports::Ports;
+
+// Shared by the GUI and the CLI, every peer on the network must use the same info port
+pub const OPTIONS_USAGE: &str = "[--ephemeral-ports] [--control-port N] [--data-port N] [--info-port N] [--discovery-port N] [--config-dir PATH]";
+
+// Ports given on the command line, they win over the config file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PortOptions {
+    pub ephemeral: bool,
+    pub control: Option<u16>,
+    pub data: Option<u16>,
+    pub info: Option<u16>,
+    pub discovery: Option<u16>,
+}
+
+impl PortOptions {
+    pub fn is_empty(&self) -> bool {
+        *self == PortOptions::default()
+    }
+
+    // On top of `ports`, usually those of the config file
+    pub fn apply(&self, ports: Ports) -> Ports {
+        let info = self.info.unwrap_or(ports.info);
+        let ports = if self.ephemeral { Ports { info, ..Ports::ephemeral() } } else { Ports { info, ..ports } };
+        Ports {
+            control: self.control.unwrap_or(ports.control),
+            data: self.data.unwrap_or(ports.data),
+            info,
+            discovery: self.discovery.unwrap_or(ports.discovery),
+        }
+    }
+}
+
+// Returns false when `arg` is not one of the shared options, it is left to the caller
+pub fn parse_option<'a>(arg: &str, args: &mut impl Iterator<Item = &'a String>, ports: &mut PortOptions) -> Result<bool, String> {
+    if arg == "--ephemeral-ports" {
+        ports.ephemeral = true;
+        return Ok(true);
+    }
+
+    let port = match arg {
+        "--control-port" => &mut ports.control,
+        "--data-port" => &mut ports.data,
+        "--info-port" => &mut ports.info,
+        "--discovery-port" => &mut ports.discovery,
+        "--config-dir" => {
+            let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+            paths::set_config_dir(PathBuf::from(value));
+            return Ok(true);
+        }
+        _ => return Ok(false),
+    };
+
+    let value = args.next().ok_or(format!("Missing value for {}", arg))?;
+    *port = Some(value.parse().map_err(|_| format!("Invalid port for {}: {}", arg, value))?);
+    Ok(true)
+}
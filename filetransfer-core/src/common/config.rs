@@ -0,0 +1,350 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+
+use crate::common::identity;
+use crate::common::paths;
+use crate::networking::ports::Ports;
+use crate::networking::server::{SymlinkPolicy, Visibility};
+
+/*
+config.toml in the config directory, every key is optional:
+
+display_name = "Office laptop"
+receive_dir = "/home/me/Downloads"
+chunk_size = 65536
+discovery_interval_secs = 3
+visibility = "visible"   # or "contacts_only", "hidden"
+ignore_untrusted_permissions = false
+trusted_peers = ["192.168.1.20"]
+symlink_policy = "inside_destination"   # or "refuse"
+
+[ports]
+control = 24934
+data = 24935
+info = 24934
+discovery = 24936
+
+Edits are picked up while running, except for the ports. The command line wins over the file.
+*/
+
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+pub const MIN_CHUNK_SIZE: usize = 4 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+pub const DEFAULT_DISCOVERY_INTERVAL_SECS: u64 = 3;
+pub const MAX_DISCOVERY_INTERVAL_SECS: u64 = 300;
+
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // Replaces the one in the identity file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    // Where received files go, the directory we were started in when missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub receive_dir: Option<PathBuf>,
+    pub chunk_size: usize,
+    pub discovery_interval_secs: u64,
+    pub visibility: Visibility,
+    // Permission bits are only applied for trusted_peers, see networking::server::ReceiverSettings
+    pub ignore_untrusted_permissions: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub trusted_peers: Vec<String>,
+    pub symlink_policy: SymlinkPolicy,
+    pub ports: Ports,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            display_name: None,
+            receive_dir: None,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            discovery_interval_secs: DEFAULT_DISCOVERY_INTERVAL_SECS,
+            visibility: Visibility::default(),
+            ignore_untrusted_permissions: false,
+            trusted_peers: Vec::new(),
+            symlink_policy: SymlinkPolicy::default(),
+            ports: Ports::default(),
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        self.display_name.as_deref().map_or(Ok(()), check_display_name)?;
+        self.receive_dir.as_deref().map_or(Ok(()), check_receive_dir)?;
+        check_chunk_size(&self.chunk_size)?;
+        check_discovery_interval(&self.discovery_interval_secs)?;
+        check_info_port(&self.ports.info)
+    }
+
+    // `self` with the most private settings, for when they can't be read: a broken file never opens up the node
+    fn restricted(&self) -> Self {
+        Config {
+            visibility: Visibility::Hidden,
+            ignore_untrusted_permissions: true,
+            trusted_peers: Vec::new(),
+            symlink_policy: SymlinkPolicy::Refuse,
+            ..self.clone()
+        }
+    }
+
+    pub fn discovery_interval(&self) -> Duration {
+        Duration::from_secs(self.discovery_interval_secs)
+    }
+}
+
+// Loaded on first use, after the command line has set the config dir
+static CURRENT: Mutex<Option<Config>> = Mutex::new(None);
+static PINNED_RECEIVE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+pub fn config_file() -> PathBuf {
+    paths::config_dir().join("config.toml")
+}
+
+fn check_display_name(name: &str) -> Result<(), String> {
+    if identity::sanitize_display_name(name).is_empty() {
+        return Err(String::from("display_name is empty"));
+    }
+    Ok(())
+}
+
+// A directory that was deleted or sits on a drive that isn't mounted
+fn check_receive_dir(dir: &Path) -> Result<(), String> {
+    if !dir.is_dir() {
+        return Err(format!("receive_dir {} is not a directory", dir.display()));
+    }
+    Ok(())
+}
+
+fn check_chunk_size(chunk_size: &usize) -> Result<(), String> {
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(chunk_size) {
+        return Err(format!("chunk_size must be between {} and {} bytes", MIN_CHUNK_SIZE, MAX_CHUNK_SIZE));
+    }
+    Ok(())
+}
+
+fn check_discovery_interval(secs: &u64) -> Result<(), String> {
+    if !(1..=MAX_DISCOVERY_INTERVAL_SECS).contains(secs) {
+        return Err(format!("discovery_interval_secs must be between 1 and {}", MAX_DISCOVERY_INTERVAL_SECS));
+    }
+    Ok(())
+}
+
+// The others can be 0 for a free port, peers ping this one blindly
+fn check_info_port(port: &u16) -> Result<(), String> {
+    if *port == 0 {
+        return Err(String::from("the info port can't be 0"));
+    }
+    Ok(())
+}
+
+fn no_check<T>(_: &T) -> Result<(), String> {
+    Ok(())
+}
+
+// Ok(None) when the key is missing. Err when its value can't be used, the reason is added to `problems`
+fn read_key<T: DeserializeOwned>(table: &toml::Table, key: &str, check: impl Fn(&T) -> Result<(), String>, problems: &mut Vec<String>) -> Result<Option<T>, ()> {
+    let Some(value) = table.get(key) else {
+        return Ok(None);
+    };
+    let value = value.clone().try_into::<T>()
+        .map_err(|e| format!("{}: {}", key, e.to_string().trim()))
+        .and_then(|value| check(&value).map(|_| value));
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            problems.push(e);
+            Err(())
+        }
+    }
+}
+
+// The default when the key is missing, `invalid` when its value can't be used
+fn pick<T>(value: Result<Option<T>, ()>, default: T, invalid: T) -> T {
+    match value {
+        Ok(value) => value.unwrap_or(default),
+        Err(()) => invalid,
+    }
+}
+
+/*
+Every key is read on its own, one that can't be used doesn't take the others with it. It keeps its value in `base`
+(the defaults at start, the previous config when the file changes), except for the visibility and the permission
+settings: those get their most private value, so a mistake never makes the node more visible or more trusting.
+A file that can't be read at all is `base` with those private values. Returns what was left out and why
+*/
+pub fn load_over(base: &Config) -> (Config, Vec<String>) {
+    let mut problems = Vec::new();
+    let content = match fs::read_to_string(config_file()) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (Config::default(), problems),
+        Err(e) => {
+            problems.push(e.to_string());
+            return (base.restricted(), problems);
+        }
+    };
+    let config = parse_over(&content, base, &mut problems);
+    (config, problems)
+}
+
+fn parse_over(content: &str, base: &Config, problems: &mut Vec<String>) -> Config {
+    let table: toml::Table = match content.parse() {
+        Ok(table) => table,
+        Err(e) => {
+            problems.push(e.to_string().trim().to_string());
+            return base.restricted();
+        }
+    };
+
+    let defaults = Config::default();
+    let restricted = base.restricted();
+    let ports = match read_key::<toml::Table>(&table, "ports", no_check, problems) {
+        Ok(ports) => {
+            let ports = ports.unwrap_or_default();
+            let mut port = |key: &str, default: u16, invalid: u16, check: fn(&u16) -> Result<(), String>| {
+                pick(read_key(&ports, key, check, problems), default, invalid)
+            };
+            Ports {
+                control: port("control", defaults.ports.control, base.ports.control, no_check),
+                data: port("data", defaults.ports.data, base.ports.data, no_check),
+                info: port("info", defaults.ports.info, base.ports.info, check_info_port),
+                discovery: port("discovery", defaults.ports.discovery, base.ports.discovery, no_check),
+            }
+        }
+        Err(()) => base.ports,
+    };
+
+    Config {
+        display_name: pick(read_key(&table, "display_name", |name: &String| check_display_name(name), problems).map(|name| name.map(Some)), None, base.display_name.clone()),
+        receive_dir: pick(read_key(&table, "receive_dir", |dir: &PathBuf| check_receive_dir(dir), problems).map(|dir| dir.map(Some)), None, base.receive_dir.clone()),
+        chunk_size: pick(read_key(&table, "chunk_size", check_chunk_size, problems), defaults.chunk_size, base.chunk_size),
+        discovery_interval_secs: pick(read_key(&table, "discovery_interval_secs", check_discovery_interval, problems), defaults.discovery_interval_secs, base.discovery_interval_secs),
+        visibility: pick(read_key(&table, "visibility", no_check, problems), defaults.visibility, restricted.visibility),
+        ignore_untrusted_permissions: pick(read_key(&table, "ignore_untrusted_permissions", no_check, problems), defaults.ignore_untrusted_permissions, restricted.ignore_untrusted_permissions),
+        trusted_peers: pick(read_key(&table, "trusted_peers", no_check, problems), defaults.trusted_peers, restricted.trusted_peers),
+        symlink_policy: pick(read_key(&table, "symlink_policy", no_check, problems), defaults.symlink_policy, restricted.symlink_policy),
+        ports,
+    }
+}
+
+// What a start without a previous config reads, see load_over
+pub fn load() -> (Config, Vec<String>) {
+    load_over(&Config::default())
+}
+
+// Validated first, a bad config is never written. Running nodes pick it up through `watch`
+pub fn save(config: &Config) -> Result<(), String> {
+    config.validate()?;
+    let content = toml::to_string_pretty(config).map_err(|e| e.to_string())?;
+    fs::create_dir_all(paths::config_dir()).map_err(|e| e.to_string())?;
+    fs::write(config_file(), content).map_err(|e| e.to_string())?;
+    *CURRENT.lock().unwrap() = Some(config.clone());
+    Ok(())
+}
+
+pub fn current() -> Config {
+    let mut current = CURRENT.lock().unwrap();
+    current.get_or_insert_with(|| {
+        let (config, problems) = load();
+        for problem in problems {
+            eprintln!("Ignoring in {}: {}", config_file().display(), problem);
+        }
+        config
+    }).clone()
+}
+
+// The receive directory was given on the command line, changes to receive_dir leave it alone
+pub fn pin_receive_dir(dir: PathBuf) {
+    let _ = PINNED_RECEIVE_DIR.set(dir);
+}
+
+pub fn receive_dir_pinned() -> bool {
+    PINNED_RECEIVE_DIR.get().is_some()
+}
+
+// The pinned directory, receive_dir or the one we were started in. Absolute, received paths never depend on the working directory
+pub fn receive_dir(config: &Config) -> PathBuf {
+    let dir = PINNED_RECEIVE_DIR.get().or(config.receive_dir.as_ref()).cloned().unwrap_or_else(|| PathBuf::from("."));
+    std::path::absolute(&dir).unwrap_or(dir)
+}
+
+fn modified() -> Option<SystemTime> {
+    fs::metadata(config_file()).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Calls `on_change` with the previous and the new config whenever the file changes. Invalid values keep
+// their previous one, or get their most private one for the visibility and permissions (see load_over)
+pub fn watch(on_change: impl Fn(&Config, &Config) + Send + 'static) {
+    thread::spawn(move || {
+        let mut previous = current();
+        let mut last_modified = modified();
+        loop {
+            thread::sleep(WATCH_INTERVAL);
+            let modified = modified();
+            if modified == last_modified {
+                continue;
+            }
+            last_modified = modified;
+
+            let (config, problems) = load_over(&previous);
+            for problem in problems {
+                eprintln!("Ignoring in {}: {}", config_file().display(), problem);
+            }
+            *CURRENT.lock().unwrap() = Some(config.clone());
+            if config != previous {
+                on_change(&previous, &config);
+                previous = config;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str, base: &Config) -> (Config, Vec<String>) {
+        let mut problems = Vec::new();
+        let config = parse_over(content, base, &mut problems);
+        (config, problems)
+    }
+
+    #[test]
+    fn keeps_the_valid_keys_next_to_an_invalid_one() {
+        let content = "receive_dir = \"/nonexistent/unmounted\"\nvisibility = \"contacts_only\"\nsymlink_policy = \"refuse\"\nchunk_size = 1\n\n[ports]\ncontrol = 25000\n";
+        let base = Config { chunk_size: 8192, ..Config::default() };
+        let (config, problems) = parse(content, &base);
+
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert_eq!(config.receive_dir, None);
+        assert_eq!(config.chunk_size, 8192);
+        assert_eq!(config.visibility, Visibility::ContactsOnly);
+        assert_eq!(config.symlink_policy, SymlinkPolicy::Refuse);
+        assert_eq!((config.ports.control, config.ports.info), (25000, crate::networking::ports::DEFAULT_INFO));
+    }
+
+    #[test]
+    fn never_gets_less_private_from_a_broken_file() {
+        let base = Config { trusted_peers: vec![String::from("192.168.1.20")], ..Config::default() };
+
+        let (config, problems) = parse("visibility = \"everyone\"\nignore_untrusted_permissions = \"yes\"\ntrusted_peers = 3\n", &base);
+        assert_eq!(problems.len(), 3);
+        assert_eq!(config.visibility, Visibility::Hidden);
+        assert!(config.ignore_untrusted_permissions && config.trusted_peers.is_empty());
+
+        let (config, problems) = parse("visibility = \"contacts_only\"\n[ports\n", &base);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(config.visibility, Visibility::Hidden);
+        assert_eq!(config.symlink_policy, SymlinkPolicy::Refuse);
+        assert!(config.ignore_untrusted_permissions && config.trusted_peers.is_empty());
+    }
+}
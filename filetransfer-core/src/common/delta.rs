@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use sha2::{Sha256, Digest};
+
+/*
+rsync-style delta transfer.
+
+The receiver splits its existing copy of the file into blocks and sends the
+signature (weak rolling checksum + SHA-256 of every block). The sender slides a
+window over the new file and emits a stream of operations:
+
+COPY  [0u8][block index: u32 LE]           reuse a block of the old file
+DATA  [1u8][length: u32 LE][bytes]         literal bytes
+END   [2u8]
+*/
+
+// Existing files smaller than this are simply sent in full
+pub const DELTA_MIN_SIZE: u64 = 1024 * 1024;
+
+const MIN_BLOCK_SIZE: u64 = 2 * 1024;
+const MAX_BLOCK_SIZE: u64 = 128 * 1024;
+const MAX_LITERAL_SIZE: usize = 64 * 1024;
+const MAX_SIGNATURE_BLOCKS: u32 = 16 * 1024 * 1024;
+
+const OP_COPY: u8 = 0;
+const OP_DATA: u8 = 1;
+const OP_END: u8 = 2;
+
+pub struct BlockSignature {
+    pub weak: u32,
+    pub strong: [u8; 32],
+}
+
+pub struct Signature {
+    pub file_len: u64,
+    pub block_size: u32,
+    pub blocks: Vec<BlockSignature>,
+}
+
+// Same heuristic as rsync: roughly sqrt(len), rounded down to a multiple of 1 KB
+pub fn block_size_for(len: u64) -> u32 {
+    let size = ((len as f64).sqrt() as u64) / 1024 * 1024;
+    size.clamp(MIN_BLOCK_SIZE, MAX_BLOCK_SIZE) as u32
+}
+
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(data: &[u8]) -> Self {
+        let len = data.len() as u32;
+        let mut a: u32 = 0;
+        let mut b: u32 = 0;
+        for (i, byte) in data.iter().enumerate() {
+            a = a.wrapping_add(*byte as u32);
+            b = b.wrapping_add((len - i as u32).wrapping_mul(*byte as u32));
+        }
+        Rolling { a, b, len }
+    }
+
+    fn roll(&mut self, out: u8, inb: u8) {
+        self.a = self.a.wrapping_sub(out as u32).wrapping_add(inb as u32);
+        self.b = self.b.wrapping_sub(self.len.wrapping_mul(out as u32)).wrapping_add(self.a);
+    }
+
+    fn digest(&self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_hash(data: &[u8]) -> [u8; 32] {
+    let mut strong = [0u8; 32];
+    strong.copy_from_slice(&Sha256::digest(data)[..]);
+    strong
+}
+
+// Like read_exact, but a short read at EOF is not an error
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+pub fn compute_signature(path: impl AsRef<Path>) -> io::Result<Signature> {
+    let mut file = File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let block_size = block_size_for(file_len);
+    let mut buffer = vec![0u8; block_size as usize];
+    let mut blocks = Vec::new();
+
+    loop {
+        let n = read_full(&mut file, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        blocks.push(BlockSignature {
+            weak: Rolling::new(&buffer[..n]).digest(),
+            strong: strong_hash(&buffer[..n]),
+        });
+    }
+
+    Ok(Signature { file_len, block_size, blocks })
+}
+
+pub fn write_signature<W: Write>(writer: &mut W, signature: &Signature) -> io::Result<()> {
+    writer.write_all(&signature.file_len.to_le_bytes())?;
+    writer.write_all(&signature.block_size.to_le_bytes())?;
+    writer.write_all(&(signature.blocks.len() as u32).to_le_bytes())?;
+    for block in &signature.blocks {
+        writer.write_all(&block.weak.to_le_bytes())?;
+        writer.write_all(&block.strong)?;
+    }
+    writer.flush()
+}
+
+pub fn read_signature<R: Read>(reader: &mut R) -> io::Result<Signature> {
+    let mut u64_buf = [0u8; 8];
+    let mut u32_buf = [0u8; 4];
+
+    reader.read_exact(&mut u64_buf)?;
+    let file_len = u64::from_le_bytes(u64_buf);
+    reader.read_exact(&mut u32_buf)?;
+    let block_size = u32::from_le_bytes(u32_buf);
+    reader.read_exact(&mut u32_buf)?;
+    let count = u32::from_le_bytes(u32_buf);
+
+    // One block per block_size bytes of the old file, as compute_signature makes it
+    if block_size == 0 || block_size as u64 > MAX_BLOCK_SIZE || count > MAX_SIGNATURE_BLOCKS || count as u64 != file_len.div_ceil(block_size as u64) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid delta signature"));
+    }
+
+    // Grows as the blocks arrive, the count comes from the peer
+    let mut blocks = Vec::new();
+    for _ in 0..count {
+        let mut strong = [0u8; 32];
+        reader.read_exact(&mut u32_buf)?;
+        reader.read_exact(&mut strong)?;
+        blocks.push(BlockSignature { weak: u32::from_le_bytes(u32_buf), strong });
+    }
+
+    Ok(Signature { file_len, block_size, blocks })
+}
+
+fn write_literal<W: Write>(out: &mut W, data: &[u8]) -> io::Result<()> {
+    for chunk in data.chunks(MAX_LITERAL_SIZE) {
+        out.write_all(&[OP_DATA])?;
+        out.write_all(&(chunk.len() as u32).to_le_bytes())?;
+        out.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+fn fill<R: Read>(src: &mut R, buf: &mut Vec<u8>, want: usize, eof: &mut bool) -> io::Result<()> {
+    let mut chunk = [0u8; 64 * 1024];
+    while buf.len() < want && !*eof {
+        let n = src.read(&mut chunk)?;
+        if n == 0 {
+            *eof = true;
+        } else {
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+    Ok(())
+}
+
+// Streams the delta of `src` against `signature` into `out`.
+// `progress` is called with the number of bytes of `src` processed so far.
+pub fn write_delta<R: Read, W: Write, F: FnMut(u64)>(src: &mut R, signature: &Signature, out: &mut W, mut progress: F) -> io::Result<()> {
+    let block_size = signature.block_size as usize;
+
+    // Only full blocks can be matched by the rolling window
+    let full_blocks = (signature.file_len / signature.block_size as u64) as usize;
+    let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (i, block) in signature.blocks.iter().enumerate().take(full_blocks) {
+        table.entry(block.weak).or_default().push(i);
+    }
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut eof = false;
+    let mut pos = 0; // start of the window in buf
+    let mut literal_start = 0; // start of the pending literal data in buf
+    let mut consumed: u64 = 0; // bytes already dropped from the front of buf
+    let mut rolling: Option<Rolling> = None;
+
+    loop {
+        fill(src, &mut buf, pos + block_size, &mut eof)?;
+        if buf.len() - pos < block_size {
+            break;
+        }
+
+        let window = &buf[pos..pos + block_size];
+        let checksum = rolling.get_or_insert_with(|| Rolling::new(window)).digest();
+
+        let mut matched = None;
+        if let Some(candidates) = table.get(&checksum) {
+            let strong = strong_hash(window);
+            matched = candidates.iter().find(|&&i| signature.blocks[i].strong == strong).copied();
+        }
+
+        if let Some(index) = matched {
+            write_literal(out, &buf[literal_start..pos])?;
+            out.write_all(&[OP_COPY])?;
+            out.write_all(&(index as u32).to_le_bytes())?;
+            pos += block_size;
+            literal_start = pos;
+            rolling = None;
+        } else {
+            fill(src, &mut buf, pos + block_size + 1, &mut eof)?;
+            if buf.len() - pos > block_size
+                && let Some(r) = rolling.as_mut()
+            {
+                r.roll(buf[pos], buf[pos + block_size]);
+            }
+            pos += 1;
+
+            if pos - literal_start >= MAX_LITERAL_SIZE {
+                write_literal(out, &buf[literal_start..pos])?;
+                literal_start = pos;
+            }
+        }
+
+        // Drop everything that has already been sent
+        if literal_start >= 4 * MAX_LITERAL_SIZE {
+            buf.drain(..literal_start);
+            pos -= literal_start;
+            consumed += literal_start as u64;
+            literal_start = 0;
+            progress(consumed);
+        }
+    }
+
+    fill(src, &mut buf, usize::MAX, &mut eof)?;
+    write_literal(out, &buf[literal_start..])?;
+    out.write_all(&[OP_END])?;
+    out.flush()?;
+
+    progress(consumed + buf.len() as u64);
+
+    Ok(())
+}
+
+// Rebuilds the new file into `out` from the delta stream and the old file, `signature` is the one sent for it.
+// Returns the number of bytes written.
+pub fn apply_delta<R: Read, W: Write, F: FnMut(u64)>(delta: &mut R, basis: &mut File, signature: &Signature, out: &mut W, mut progress: F) -> io::Result<u64> {
+    let block_size = signature.block_size;
+    let mut block = vec![0u8; block_size as usize];
+    let mut literal = vec![0u8; MAX_LITERAL_SIZE];
+    let mut op = [0u8; 1];
+    let mut u32_buf = [0u8; 4];
+    let mut written: u64 = 0;
+
+    loop {
+        delta.read_exact(&mut op)?;
+        match op[0] {
+            OP_COPY => {
+                delta.read_exact(&mut u32_buf)?;
+                let index = u32::from_le_bytes(u32_buf) as u64;
+                // Caught here rather than by the final hash, after the whole transfer
+                if index >= signature.blocks.len() as u64 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Delta block index out of range"));
+                }
+                basis.seek(SeekFrom::Start(index * block_size as u64))?;
+                let n = read_full(basis, &mut block)?;
+                out.write_all(&block[..n])?;
+                written += n as u64;
+            }
+            OP_DATA => {
+                delta.read_exact(&mut u32_buf)?;
+                let len = u32::from_le_bytes(u32_buf) as usize;
+                if len > MAX_LITERAL_SIZE {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Delta literal too large"));
+                }
+                delta.read_exact(&mut literal[..len])?;
+                out.write_all(&literal[..len])?;
+                written += len as u64;
+            }
+            OP_END => break,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown delta operation")),
+        }
+        progress(written);
+    }
+
+    out.flush()?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    // Deterministic bytes that don't repeat within a block
+    fn pseudo_random(len: usize, seed: u32) -> Vec<u8> {
+        let mut state = seed;
+        (0..len).map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        }).collect()
+    }
+
+    fn basis_file(name: &str, data: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ft-delta-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    // Signature of `old`, delta of `new` against it, and `new` rebuilt from both
+    fn round_trip(name: &str, old: &[u8], new: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let path = basis_file(name, old);
+        let signature = compute_signature(&path).unwrap();
+
+        let mut delta = Vec::new();
+        write_delta(&mut Cursor::new(new), &signature, &mut delta, |_| ()).unwrap();
+
+        let mut rebuilt = Vec::new();
+        let mut basis = File::open(&path).unwrap();
+        let written = apply_delta(&mut Cursor::new(&delta), &mut basis, &signature, &mut rebuilt, |_| ()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, new.len() as u64);
+        (delta, rebuilt)
+    }
+
+    #[test]
+    fn rebuilds_an_edited_file_from_a_small_delta() {
+        let old = pseudo_random(512 * 1024, 1);
+        let mut new = old.clone();
+        new[100_000..100_010].copy_from_slice(b"0123456789");
+        new.splice(300_000..300_000, b"inserted in the middle".iter().copied());
+        new.extend_from_slice(b"appended at the end");
+
+        let (delta, rebuilt) = round_trip("edited", &old, &new);
+        assert_eq!(rebuilt, new);
+        assert!(delta.len() < new.len() / 10, "delta is {} bytes", delta.len());
+    }
+
+    #[test]
+    fn rebuilds_unrelated_and_empty_files() {
+        let old = pseudo_random(64 * 1024, 2);
+
+        let new = pseudo_random(100 * 1024, 3);
+        assert_eq!(round_trip("unrelated", &old, &new).1, new);
+        assert_eq!(round_trip("empty", &old, &[]).1, Vec::<u8>::new());
+    }
+
+    #[test]
+    fn keeps_a_partial_last_block() {
+        let old = pseudo_random(10_000, 4);
+        let (_, rebuilt) = round_trip("partial", &old, &old);
+        assert_eq!(rebuilt, old);
+    }
+
+    #[test]
+    fn signature_survives_the_wire() {
+        let path = basis_file("signature", &pseudo_random(20_000, 5));
+        let signature = compute_signature(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut wire = Vec::new();
+        write_signature(&mut wire, &signature).unwrap();
+        let read = read_signature(&mut Cursor::new(&wire)).unwrap();
+
+        assert_eq!(read.file_len, 20_000);
+        assert_eq!(read.block_size, signature.block_size);
+        assert_eq!(read.blocks.len(), signature.blocks.len());
+        for (a, b) in read.blocks.iter().zip(signature.blocks.iter()) {
+            assert_eq!((a.weak, a.strong), (b.weak, b.strong));
+        }
+    }
+
+    #[test]
+    fn refuses_a_block_index_outside_the_signature() {
+        let path = basis_file("bad-index", &pseudo_random(10_000, 6));
+        let signature = compute_signature(&path).unwrap();
+
+        let mut delta = vec![OP_COPY];
+        delta.extend_from_slice(&(signature.blocks.len() as u32).to_le_bytes());
+        delta.push(OP_END);
+
+        let mut basis = File::open(&path).unwrap();
+        let error = apply_delta(&mut Cursor::new(&delta), &mut basis, &signature, &mut Vec::new(), |_| ()).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn refuses_an_invalid_signature() {
+        let mut wire = Vec::new();
+        wire.extend_from_slice(&1000u64.to_le_bytes());
+        wire.extend_from_slice(&0u32.to_le_bytes());
+        wire.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(read_signature(&mut Cursor::new(&wire)).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn refuses_a_block_count_not_matching_the_length() {
+        let mut wire = Vec::new();
+        wire.extend_from_slice(&1000u64.to_le_bytes());
+        wire.extend_from_slice(&(MIN_BLOCK_SIZE as u32).to_le_bytes());
+        wire.extend_from_slice(&MAX_SIGNATURE_BLOCKS.to_le_bytes());
+        assert_eq!(read_signature(&mut Cursor::new(&wire)).err().map(|e| e.kind()), Some(io::ErrorKind::InvalidData));
+    }
+}
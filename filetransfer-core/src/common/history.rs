@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::hash::bytes_to_hex;
+use crate::common::manifest::EntryKind;
+use crate::common::paths;
+use crate::common::transfer_state::{TransferState, TransferType};
+use crate::engine::State;
+use crate::engine::events::Event;
+use crate::networking::client::PingResponse;
+
+/*
+Every finished transfer, sent or received, one JSON object per line in the config directory.
+Only ever appended to, by the node that did the transfer:
+
+{"finished_at":1760000000,"direction":"received","peer_name":"Office laptop","peer_address":"192.168.1.20",
+ "peer_device_id":"5f1c...","file":"report.pdf","path":"/home/me/Downloads/report.pdf","size":1048576,
+ "hash":"9f86...","duration_secs":1.2,"error":null}
+
+Past MAX_FILE_SIZE the file is renamed to history.1.jsonl, replacing the previous one, and a new one is started.
+*/
+
+pub const MAX_FILE_SIZE: u64 = 4 * 1024 * 1024;
+// Kept in memory by a window, about what the two files hold
+pub const MAX_ENTRIES: usize = 20_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    // Seconds since the Unix epoch
+    pub finished_at: u64,
+    pub direction: Direction,
+    pub peer_name: String,
+    pub peer_address: String,
+    // Empty for peers running an older version
+    pub peer_device_id: String,
+    pub file: String,
+    // Where the file ended up, the local original for sent files
+    pub path: String,
+    pub size: u64,
+    // Hex SHA-256, missing for symlinks
+    pub hash: Option<String>,
+    pub duration_secs: f64,
+    // Set when the transfer failed
+    pub error: Option<String>,
+}
+
+impl HistoryEntry {
+    // `peer_name` or `peer_address`, whichever is known
+    pub fn peer(&self) -> &str {
+        if self.peer_name.is_empty() { &self.peer_address } else { &self.peer_name }
+    }
+
+    // What Node::find_peer takes to find the same peer again
+    pub fn peer_query(&self) -> &str {
+        if self.peer_device_id.is_empty() { &self.peer_address } else { &self.peer_device_id }
+    }
+}
+
+pub fn history_file() -> PathBuf {
+    paths::config_dir().join("history.jsonl")
+}
+
+fn rotated_file() -> PathBuf {
+    paths::config_dir().join("history.1.jsonl")
+}
+
+// Oldest first, lines that don't parse (e.g. cut by a crash) are skipped
+pub fn load() -> Vec<HistoryEntry> {
+    HistoryReader::default().read_new()
+}
+
+// Follows the history file, whoever appends to it (this node, a daemon, another instance)
+#[derive(Default)]
+pub struct HistoryReader {
+    // Bytes of the current file already read, None before the first read
+    offset: Option<u64>,
+    // First line of the current file, a different one means it was rotated. Empty until it has one
+    first_line: Vec<u8>,
+}
+
+impl HistoryReader {
+    // Every entry on the first call, then the ones appended since. Oldest first
+    pub fn read_new(&mut self) -> Vec<HistoryEntry> {
+        self.read_new_from(&history_file(), &rotated_file())
+    }
+
+    fn read_new_from(&mut self, current: &Path, rotated: &Path) -> Vec<HistoryEntry> {
+        let mut entries = Vec::new();
+        let offset = match self.offset {
+            None => {
+                read_lines(rotated, 0, &mut entries);
+                0
+            }
+            // Another file or shorter than what was read, it was rotated: the rest of the old file is in the rotated one.
+            // The new one may already be longer than the old offset, only the first line tells them apart then
+            Some(offset) if (!self.first_line.is_empty() && first_line(current) != self.first_line)
+                || fs::metadata(current).map_or(0, |metadata| metadata.len()) < offset =>
+            {
+                read_lines(rotated, offset, &mut entries);
+                self.first_line.clear();
+                0
+            }
+            Some(offset) => offset,
+        };
+        self.offset = Some(offset + read_lines(current, offset, &mut entries));
+        if self.first_line.is_empty() {
+            self.first_line = first_line(current);
+        }
+        entries
+    }
+}
+
+// Empty while the file has no complete line
+fn first_line(path: &Path) -> Vec<u8> {
+    let mut line = Vec::new();
+    let complete = fs::File::open(path)
+        .and_then(|file| io::BufReader::new(file).read_until(b'\n', &mut line))
+        .is_ok_and(|_| line.ends_with(b"\n"));
+    if complete { line } else { Vec::new() }
+}
+
+// The complete lines from `offset`, a line still being written is left for the next read. Returns the bytes read
+fn read_lines(path: &Path, offset: u64, entries: &mut Vec<HistoryEntry>) -> u64 {
+    let Ok(mut file) = fs::File::open(path) else {
+        return 0;
+    };
+    let mut content = Vec::new();
+    if file.seek(SeekFrom::Start(offset)).and_then(|_| file.read_to_end(&mut content)).is_err() {
+        return 0;
+    }
+    let complete = content.iter().rposition(|byte| *byte == b'\n').map_or(0, |end| end + 1);
+    entries.extend(content[..complete]
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| serde_json::from_slice(line).ok()));
+    complete as u64
+}
+
+pub fn append(entry: &HistoryEntry) -> io::Result<()> {
+    fs::create_dir_all(paths::config_dir())?;
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    if fs::metadata(history_file()).is_ok_and(|metadata| metadata.len() >= MAX_FILE_SIZE) {
+        fs::rename(history_file(), rotated_file())?;
+    }
+    // One write per entry, several instances may share the file
+    OpenOptions::new().create(true).append(true).open(history_file())?.write_all(line.as_bytes())
+}
+
+// Builds the entries from the events of one node, see recorder
+#[derive(Default)]
+pub struct Recorder {
+    state: State,
+    // Who sent each request and the hashes of its files, the local copies don't carry them
+    senders: HashMap<u32, PingResponse>,
+    hashes: HashMap<(u32, String), [u8; 32]>,
+    // Files of each request that didn't finish yet, the request is forgotten once none is left
+    open: HashMap<u32, HashSet<String>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    // `find_peer` names the peers of sent files, they are only known by address
+    pub fn record(&mut self, event: &Event, find_peer: impl Fn(IpAddr) -> Option<PingResponse>) -> Option<HistoryEntry> {
+        self.state.apply(event);
+
+        let id = match event {
+            Event::RequestReceived { id, request } => {
+                self.senders.insert(*id, request.from.clone());
+                for file in request.files.iter().filter(|file| matches!(file.kind, EntryKind::File)) {
+                    self.hashes.insert((*id, file.name.clone()), file.hash);
+                }
+                self.open.insert(*id, request.files.iter().map(|file| file.name.clone()).collect());
+                return None;
+            }
+            // Every accepted file ends up in a transfer, the others never will
+            Event::RequestClosed { id, accepted_files } => {
+                if let Some(open) = self.open.get_mut(id) {
+                    open.retain(|name| accepted_files.contains(name));
+                }
+                self.close_if_done(*id);
+                return None;
+            }
+            Event::TransferAdded { id, .. } | Event::TransferChanged { id, .. } => *id,
+            _ => return None,
+        };
+
+        let transfer = self.state.transfers.get(&id).filter(|t| t.ttype.is_finished())?.clone();
+        // Recorded once, the recorder would grow as much as the history otherwise
+        self.state.transfers.remove(&id);
+
+        let received = match &transfer.ttype {
+            TransferType::CompletelyReceived => true,
+            TransferType::CompletelySent => false,
+            _ => transfer.batch.is_some_and(|batch| self.senders.contains_key(&batch)) || transfer.original_filepath.is_empty(),
+        };
+        let peer = transfer.batch
+            .filter(|_| received)
+            .and_then(|batch| self.senders.get(&batch).cloned())
+            .or_else(|| find_peer(transfer.peer.ip()));
+
+        let entry = self.entry(&transfer, received, peer);
+        if let Some(batch) = transfer.batch.filter(|_| received) {
+            if let Some(open) = self.open.get_mut(&batch) {
+                open.remove(&entry.file);
+            }
+            self.close_if_done(batch);
+        }
+        Some(entry)
+    }
+
+    fn close_if_done(&mut self, id: u32) {
+        if self.open.get(&id).is_some_and(|open| !open.is_empty()) {
+            return;
+        }
+        self.open.remove(&id);
+        self.senders.remove(&id);
+        self.hashes.retain(|(request, _), _| *request != id);
+    }
+
+    fn entry(&self, transfer: &TransferState, received: bool, peer: Option<PingResponse>) -> HistoryEntry {
+        // Received files are saved under the receive directory, sent ones may be relative to where we were started
+        let path = if received {
+            PathBuf::from(&transfer.dest_filepath)
+        } else {
+            std::path::absolute(&transfer.original_filepath).unwrap_or_else(|_| PathBuf::from(&transfer.original_filepath))
+        };
+        let file = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+
+        let hash = transfer.hash.or_else(|| {
+            let batch = transfer.batch?;
+            self.hashes.get(&(batch, file.clone())).copied()
+        });
+
+        HistoryEntry {
+            finished_at: transfer.ended_at.unwrap_or_else(SystemTime::now).duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+            direction: if received { Direction::Received } else { Direction::Sent },
+            peer_name: peer.as_ref().map(|peer| peer.name().to_string()).unwrap_or_default(),
+            peer_address: peer.as_ref().map(|peer| peer.addr.ip()).unwrap_or(transfer.peer.ip()).to_string(),
+            peer_device_id: peer.map(|peer| peer.device_id).unwrap_or_default(),
+            file,
+            path: path.to_string_lossy().into_owned(),
+            size: transfer.bytes_total,
+            hash: hash.filter(|hash| *hash != [0u8; 32]).map(|hash| bytes_to_hex(&hash)),
+            duration_secs: transfer.duration().as_secs_f64(),
+            error: transfer.error_text(),
+        }
+    }
+}
+
+// Saved before the subscribers learn that the transfer ended, a command exiting right after doesn't lose it
+pub fn recorder(find_peer: impl Fn(IpAddr) -> Option<PingResponse>) -> impl FnMut(&Event) {
+    let mut recorder = Recorder::new();
+    move |event| {
+        if let Some(entry) = recorder.record(event, &find_peer)
+            && let Err(e) = append(&entry)
+        {
+            eprintln!("Cannot save the transfer history: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::manifest::FileEntry;
+    use crate::networking::server::RequestData;
+
+    fn request(names: &[&str]) -> Event {
+        let from = PingResponse::new("192.168.1.20:24934".parse().unwrap(), String::new(), String::from("office"), String::new());
+        let files = names.iter()
+            .map(|name| FileEntry { name: name.to_string(), size: 1, hash: [9u8; 32], kind: EntryKind::File, xattrs: Vec::new(), metadata: None })
+            .collect();
+        Event::RequestReceived { id: 1, request: RequestData { from, files } }
+    }
+
+    fn received(id: u32, name: &str) -> Event {
+        let mut state = TransferState::new(TransferType::CompletelyReceived, String::new(), format!("/tmp/{}", name), "192.168.1.20:40000".parse().unwrap(), 1);
+        state.batch = Some(1);
+        Event::TransferAdded { id, state }
+    }
+
+    #[test]
+    fn forgets_a_request_once_its_accepted_files_finished() {
+        let mut recorder = Recorder::new();
+        recorder.record(&request(&["a", "b", "c"]), |_| None);
+        recorder.record(&Event::RequestClosed { id: 1, accepted_files: vec![String::from("a"), String::from("b")] }, |_| None);
+
+        let entry = recorder.record(&received(10, "a"), |_| None).unwrap();
+        assert_eq!((entry.direction, entry.peer_name.as_str()), (Direction::Received, "office"));
+        assert_eq!(entry.hash, Some(bytes_to_hex(&[9u8; 32])));
+        assert!(recorder.senders.contains_key(&1));
+
+        recorder.record(&received(11, "b"), |_| None).unwrap();
+        assert!(recorder.senders.is_empty() && recorder.hashes.is_empty() && recorder.open.is_empty());
+    }
+
+    #[test]
+    fn reads_only_complete_lines() {
+        let path = std::env::temp_dir().join(format!("ft-history-{}.jsonl", std::process::id()));
+        let mut recorder = Recorder::new();
+        recorder.record(&request(&["a"]), |_| None);
+        recorder.record(&Event::RequestClosed { id: 1, accepted_files: vec![String::from("a")] }, |_| None);
+        let line = serde_json::to_string(&recorder.record(&received(10, "a"), |_| None).unwrap()).unwrap();
+
+        fs::write(&path, format!("{}\n{}", line, &line[..10])).unwrap();
+        let mut entries = Vec::new();
+        let read = read_lines(&path, 0, &mut entries);
+        assert_eq!((entries.len(), read), (1, line.len() as u64 + 1));
+
+        // The rest of the line was written meanwhile
+        fs::write(&path, format!("{}\n{}\n", line, line)).unwrap();
+        assert_eq!(read_lines(&path, read, &mut entries), read);
+        assert_eq!(entries.len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn notices_a_rotated_file_longer_than_the_old_offset() {
+        let dir = std::env::temp_dir().join(format!("ft-history-rotation-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (current, rotated) = (dir.join("history.jsonl"), dir.join("history.1.jsonl"));
+        let _ = fs::remove_file(&rotated);
+        let line = |n: u32| {
+            let entry = HistoryEntry { finished_at: n as u64, direction: Direction::Sent, peer_name: String::new(), peer_address: String::from("192.168.1.20"), peer_device_id: String::new(), file: format!("{}", n), path: format!("/tmp/{}", n), size: 1, hash: None, duration_secs: 0.0, error: None };
+            serde_json::to_string(&entry).unwrap() + "\n"
+        };
+
+        fs::write(&current, line(1)).unwrap();
+        let mut reader = HistoryReader::default();
+        assert_eq!(reader.read_new_from(&current, &rotated).len(), 1);
+
+        // Rotated, then the new file grew past what was read of the old one
+        fs::write(&current, line(1) + &line(2)).unwrap();
+        fs::rename(&current, &rotated).unwrap();
+        fs::write(&current, line(3) + &line(4) + &line(5)).unwrap();
+        let finished_at = reader.read_new_from(&current, &rotated).iter().map(|entry| entry.finished_at).collect::<Vec<_>>();
+        assert_eq!(finished_at, vec![2, 3, 4, 5]);
+
+        fs::write(&current, line(3) + &line(4) + &line(5) + &line(6)).unwrap();
+        assert_eq!(reader.read_new_from(&current, &rotated).len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn forgets_a_rejected_request() {
+        let mut recorder = Recorder::new();
+        recorder.record(&request(&["a"]), |_| None);
+        recorder.record(&Event::RequestClosed { id: 1, accepted_files: Vec::new() }, |_| None);
+        assert!(recorder.senders.is_empty() && recorder.hashes.is_empty() && recorder.open.is_empty());
+    }
+}
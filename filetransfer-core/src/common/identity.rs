@@ -0,0 +1,100 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::common::hash::bytes_to_hex;
+use crate::common::paths;
+
+/*
+Stored in the config directory as key=value lines:
+
+device_id=5f1c0a9e2b7d4c3a8e6f1b2d3c4a5e6f
+display_name=Office laptop
+pairing_key=0c7d2e9f4a1b6c3d8e5f2a7b4c9d1e6f
+
+The pairing key signs our pings, contacts that know it can find us in contacts only mode.
+*/
+
+const MAX_DISPLAY_NAME: usize = 64;
+
+// How this device shows up on the other peers, the ID never changes once created
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub device_id: String,
+    pub display_name: String,
+    pub pairing_key: String,
+}
+
+fn identity_file() -> PathBuf {
+    paths::config_dir().join("identity")
+}
+
+fn new_random_hex() -> String {
+    bytes_to_hex(&rand::random::<[u8; 16]>())
+}
+
+// Names travel in line based messages, keep them on a single line
+pub fn sanitize_display_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_DISPLAY_NAME)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+impl Identity {
+    pub fn load_or_create() -> Self {
+        let mut identity = Identity {
+            device_id: String::new(),
+            display_name: String::new(),
+            pairing_key: String::new(),
+        };
+
+        if let Ok(content) = fs::read_to_string(identity_file()) {
+            for line in content.lines() {
+                match line.split_once('=') {
+                    Some(("device_id", value)) => identity.device_id = value.trim().to_string(),
+                    Some(("display_name", value)) => identity.display_name = sanitize_display_name(value),
+                    Some(("pairing_key", value)) => identity.pairing_key = value.trim().to_string(),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut changed = false;
+        if identity.device_id.is_empty() {
+            identity.device_id = new_random_hex();
+            changed = true;
+        }
+        if identity.pairing_key.is_empty() {
+            identity.pairing_key = new_random_hex();
+            changed = true;
+        }
+        if identity.display_name.is_empty() {
+            identity.display_name = sanitize_display_name(&whoami::devicename());
+            changed = true;
+        }
+
+        if changed
+            && let Err(e) = identity.save()
+        {
+            eprintln!("Cannot save the device identity: {}", e);
+        }
+
+        identity
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(paths::config_dir())?;
+        fs::write(identity_file(), format!("device_id={}\ndisplay_name={}\npairing_key={}\n", self.device_id, self.display_name, self.pairing_key))?;
+
+        // The pairing key is a secret
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(identity_file(), fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::BufRead;
+use std::path::{Component, Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::hash::{bytes_to_hex, hex_to_bytes, hex_to_hash};
+use crate::common::metadata::FileMetadata;
+use crate::common::xattrs::Xattrs;
+
+/*
+request:
+ask the server if he wants to receive N entries. filename, size and SHA-256 of the content are specified.
+An entry can be followed by optional attribute lines starting with a tab (values are hex encoded):
+SYMLINK makes the entry a symlink (sent as is, never followed), XATTR is an extended attribute,
+META the permissions and times of the file (see common::metadata), for the copies that get no data header.
+An empty line ends the request.
+
+FILES N\n
+File1.pdf 238974619 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08\n
+\tXATTR user.tag 696d706f7274616e74\n
+\tMETA a4010000...\n
+...
+FileN.lnk 0 0000000000000000000000000000000000000000000000000000000000000000\n
+\tSYMLINK 46696c65312e706466\n
+\n
+
+response:
+send the client the list of the files the server is willing to receive.
+SEND files must go over the wire, HAVE files have content the server already has
+(a duplicate in the same batch or a file in its receive directory) or are symlinks
+that were created without any data.
+
+ACCEPT\n
+SEND File2.pdf\n
+HAVE File3.pdf\n
+*/
+
+// Requests bigger than this are refused instead of being read forever
+pub const MAX_FILES: usize = 100_000;
+const MAX_XATTRS: usize = 64;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EntryKind {
+    File,
+    Symlink(String),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FileEntry {
+    pub name: String,
+    pub size: u64,
+    pub hash: [u8; 32],
+    pub kind: EntryKind,
+    pub xattrs: Xattrs,
+    // None from older senders
+    #[serde(default)]
+    pub metadata: Option<FileMetadata>,
+}
+
+// Names are plain file names, a peer can't make us write outside the receive directory
+pub fn is_safe_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(components.next(), Some(Component::Normal(_))) && components.next().is_none() && !name.contains(['/', '\\'])
+}
+
+// A relative target that only goes down from the directory the link is created in.
+// `..` is refused anywhere, links of the same batch could be chained (a -> ., b -> a/..) to climb out
+pub fn symlink_stays_inside(target: &str) -> bool {
+    !target.is_empty() && Path::new(target).components().all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+pub fn encode_request(files: &[FileEntry]) -> String {
+    let mut request = format!("FILES {}\n", files.len());
+    for file in files {
+        request.push_str(&format!("{} {} {}\n", file.name, file.size, bytes_to_hex(&file.hash)));
+        if let EntryKind::Symlink(target) = &file.kind {
+            request.push_str(&format!("\tSYMLINK {}\n", bytes_to_hex(target.as_bytes())));
+        }
+        for (name, value) in &file.xattrs {
+            request.push_str(&format!("\tXATTR {} {}\n", name, bytes_to_hex(value)));
+        }
+        if let Some(metadata) = &file.metadata {
+            request.push_str(&format!("\tMETA {}\n", bytes_to_hex(&metadata.to_bytes())));
+        }
+    }
+    request.push('\n');
+    request
+}
+
+fn parse_entry(line: &str) -> Option<FileEntry> {
+    let mut parts = line.rsplitn(3, ' '); // split from the right, the name may contain spaces
+    let hash = hex_to_hash(parts.next()?)?;
+    let size = parts.next()?.parse().ok()?;
+    let name = parts.next()?.to_string();
+    Some(FileEntry { name, size, hash, kind: EntryKind::File, xattrs: Vec::new(), metadata: None })
+}
+
+fn parse_attribute(entry: &mut FileEntry, line: &str) -> Option<()> {
+    let mut parts = line.split(' ');
+    match parts.next()? {
+        "SYMLINK" => {
+            let target = String::from_utf8(hex_to_bytes(parts.next()?)?).ok()?;
+            entry.kind = EntryKind::Symlink(target);
+        }
+        "XATTR" => {
+            let name = parts.next()?.to_string();
+            let value = hex_to_bytes(parts.next()?)?;
+            if entry.xattrs.len() < MAX_XATTRS {
+                entry.xattrs.push((name, value));
+            }
+        }
+        "META" => {
+            let bytes: [u8; FileMetadata::SIZE] = hex_to_bytes(parts.next()?)?.try_into().ok()?;
+            entry.metadata = Some(FileMetadata::from_bytes(&bytes));
+        }
+        _ => {} // Unknown attributes are ignored
+    }
+    Some(())
+}
+
+pub fn read_request<R: BufRead>(reader: &mut R) -> std::io::Result<Vec<FileEntry>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let first_line = line.trim();
+    let mut files: Vec<FileEntry> = Vec::new();
+    let Some(count) = first_line.strip_prefix("FILES") else {
+        return Ok(files);
+    };
+    let num_files: usize = count.trim().parse().unwrap_or(0).min(MAX_FILES);
+
+    let mut entries_read = 0;
+    let mut last_accepted = false;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let content = line.trim_end_matches(['\r', '\n']);
+        if content.is_empty() {
+            break;
+        }
+
+        if let Some(attribute) = content.strip_prefix('\t') {
+            if let Some(entry) = files.last_mut().filter(|_| last_accepted)
+                && parse_attribute(entry, attribute).is_none()
+            {
+                eprintln!("Malformed attribute for {}: {}", entry.name, attribute);
+            }
+            continue;
+        }
+
+        entries_read += 1;
+        if entries_read > num_files {
+            break;
+        }
+
+        match parse_entry(content) {
+            Some(entry) if is_safe_name(&entry.name) => {
+                files.push(entry);
+                last_accepted = true;
+            }
+            _ => {
+                eprintln!("Malformed request line: {}", content);
+                last_accepted = false;
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+pub fn encode_response(send: &[String], have: &[String]) -> String {
+    if send.is_empty() && have.is_empty() {
+        return String::from("REJECT\n");
+    }
+
+    let mut response = String::from("ACCEPT\n");
+    for file in send {
+        response.push_str(&format!("SEND {}\n", file));
+    }
+    for file in have {
+        response.push_str(&format!("HAVE {}\n", file));
+    }
+    response
+}
+
+// Returns the files to send and the files the server already has
+pub fn parse_response(response: &str) -> (Vec<String>, Vec<String>) {
+    let mut send = Vec::new();
+    let mut have = Vec::new();
+    for line in response.lines().skip(1) {
+        if let Some(name) = line.strip_prefix("SEND ") {
+            send.push(name.to_string());
+        } else if let Some(name) = line.strip_prefix("HAVE ") {
+            have.push(name.to_string());
+        }
+    }
+    (send, have)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn entry(name: &str, size: u64, kind: EntryKind) -> FileEntry {
+        FileEntry { name: name.to_string(), size, hash: [7u8; 32], kind, xattrs: Vec::new(), metadata: None }
+    }
+
+    #[test]
+    fn request_survives_the_wire() {
+        let mut file = entry("My report.pdf", 1234, EntryKind::File);
+        file.xattrs.push((String::from("user.tag"), b"important".to_vec()));
+        let modified = std::time::UNIX_EPOCH + std::time::Duration::new(1_700_000_000, 5);
+        file.metadata = Some(FileMetadata { mode: 0o755, modified: Some(modified), accessed: None });
+        let link = entry("latest", 0, EntryKind::Symlink(String::from("My report.pdf")));
+
+        let request = encode_request(&[file, link]);
+        let files = read_request(&mut Cursor::new(request)).unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!((files[0].name.as_str(), files[0].size, files[0].hash), ("My report.pdf", 1234, [7u8; 32]));
+        assert_eq!(files[0].kind, EntryKind::File);
+        assert_eq!(files[0].xattrs, vec![(String::from("user.tag"), b"important".to_vec())]);
+        assert_eq!(files[0].metadata, Some(FileMetadata { mode: 0o755, modified: Some(modified), accessed: None }));
+        assert_eq!(files[1].metadata, None);
+        assert_eq!(files[1].kind, EntryKind::Symlink(String::from("My report.pdf")));
+    }
+
+    #[test]
+    fn skips_unsafe_names_and_their_attributes() {
+        let request = format!(
+            "FILES 3\n../evil 1 {h}\n\tSYMLINK 2f\nok.txt 1 {h}\nbroken line\n\n",
+            h = bytes_to_hex(&[1u8; 32]),
+        );
+        let files = read_request(&mut Cursor::new(request)).unwrap();
+        assert_eq!(files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), vec!["ok.txt"]);
+        assert_eq!(files[0].kind, EntryKind::File);
+    }
+
+    #[test]
+    fn stops_after_the_announced_count() {
+        let h = bytes_to_hex(&[1u8; 32]);
+        let request = format!("FILES 1\na 1 {h}\nb 1 {h}\n\n");
+        assert_eq!(read_request(&mut Cursor::new(request)).unwrap().len(), 1);
+        assert!(read_request(&mut Cursor::new("HELLO\n")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn names_must_be_plain_file_names() {
+        assert!(is_safe_name("file.txt"));
+        assert!(is_safe_name("with spaces.txt"));
+        for name in ["", ".", "..", "../x", "dir/file", "dir\\file", "/etc/passwd"] {
+            assert!(!is_safe_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn symlinks_only_point_down() {
+        for target in ["file", "./file", "dir/file", "a/./b"] {
+            assert!(symlink_stays_inside(target), "{}", target);
+        }
+        // b -> a/.. stays inside on its own, but a -> . makes it climb out
+        for target in ["", "/etc/passwd", "..", "../x", "a/..", "a/../b", "a/../../x"] {
+            assert!(!symlink_stays_inside(target), "{}", target);
+        }
+    }
+
+    #[test]
+    fn response_survives_the_wire() {
+        let send = vec![String::from("a b.txt")];
+        let have = vec![String::from("c")];
+        assert_eq!(parse_response(&encode_response(&send, &have)), (send, have));
+        assert_eq!(encode_response(&[], &[]), "REJECT\n");
+    }
+}
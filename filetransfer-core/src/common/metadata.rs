@@ -0,0 +1,113 @@
+use std::fs::{File, FileTimes};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+/*
+Sent in the data header right after the hash (28 bytes, little endian), and hex encoded
+in the META attribute of the request (see common::manifest) for the files copied locally:
+
+mode         u32  permission bits, 0 if the sender doesn't have them (e.g. Windows)
+mtime secs   i64
+mtime nanos  u32
+atime secs   i64
+atime nanos  u32
+*/
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub mode: u32,
+    pub modified: Option<SystemTime>,
+    pub accessed: Option<SystemTime>,
+}
+
+fn to_unix(time: Option<SystemTime>) -> (i64, u32) {
+    match time.and_then(|t| t.duration_since(UNIX_EPOCH).ok()) {
+        Some(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        None => (0, 0),
+    }
+}
+
+fn from_unix(secs: i64, nanos: u32) -> Option<SystemTime> {
+    if secs <= 0 || nanos >= 1_000_000_000 {
+        return None;
+    }
+    UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+}
+
+impl FileMetadata {
+    pub const SIZE: usize = 28;
+
+    pub fn from_file(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            metadata.permissions().mode() & 0o7777
+        };
+        #[cfg(not(unix))]
+        let mode = 0;
+
+        FileMetadata {
+            mode,
+            modified: metadata.modified().ok(),
+            accessed: metadata.accessed().ok(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let (mtime_secs, mtime_nanos) = to_unix(self.modified);
+        let (atime_secs, atime_nanos) = to_unix(self.accessed);
+
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.mode.to_le_bytes());
+        bytes[4..12].copy_from_slice(&mtime_secs.to_le_bytes());
+        bytes[12..16].copy_from_slice(&mtime_nanos.to_le_bytes());
+        bytes[16..24].copy_from_slice(&atime_secs.to_le_bytes());
+        bytes[24..28].copy_from_slice(&atime_nanos.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        let i64_at = |i: usize| i64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+        FileMetadata {
+            mode: u32_at(0),
+            modified: from_unix(i64_at(4), u32_at(12)),
+            accessed: from_unix(i64_at(16), u32_at(24)),
+        }
+    }
+
+    // Times are always restored, permissions only when `apply_permissions` is set
+    pub fn apply(&self, path: impl AsRef<Path>, apply_permissions: bool) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let mut times = FileTimes::new();
+        if let Some(modified) = self.modified {
+            times = times.set_modified(modified);
+        }
+        if let Some(accessed) = self.accessed {
+            times = times.set_accessed(accessed);
+        }
+
+        // Must happen before the permissions, the file could become read only
+        File::options().write(true).open(path)?.set_times(times)?;
+
+        if !apply_permissions || self.mode == 0 {
+            return Ok(());
+        }
+
+        let mut permissions = std::fs::metadata(path)?.permissions();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // Never take setuid, setgid or sticky bits from a peer
+            permissions.set_mode(self.mode & 0o777);
+        }
+        #[cfg(not(unix))]
+        permissions.set_readonly(self.mode & 0o222 == 0);
+
+        std::fs::set_permissions(path, permissions)
+    }
+}
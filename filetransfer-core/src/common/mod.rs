@@ -0,0 +1,15 @@
+pub mod hash;
+pub mod transfer_state;
+pub mod counter;
+pub mod delta;
+pub mod manifest;
+pub mod metadata;
+pub mod xattrs;
+pub mod sparse;
+pub mod paths;
+pub mod identity;
+pub mod rate;
+pub mod notify;
+pub mod args;
+pub mod config;
+pub mod history;
\ No newline at end of file
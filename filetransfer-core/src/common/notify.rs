@@ -0,0 +1,9 @@
+use std::sync::Arc;
+
+// Called by background threads after they change shared state, e.g. to repaint a window
+pub type Notify = Arc<dyn Fn() + Send + Sync>;
+
+// For frontends that poll
+pub fn none() -> Notify {
+    Arc::new(|| {})
+}
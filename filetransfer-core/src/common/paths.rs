@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static CONFIG_DIR_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
+// Lets several instances on one host keep their own identity, must be called before anything is loaded
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR_OVERRIDE.set(dir);
+}
+
+// ~/.config/filetransfer on Linux, the platform equivalent elsewhere
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = CONFIG_DIR_OVERRIDE.get() {
+        return dir.clone();
+    }
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("filetransfer")
+}
+
+// The control socket of `ft daemon`, in the runtime dir unless the config dir was moved for a second instance
+pub fn daemon_socket() -> PathBuf {
+    match (CONFIG_DIR_OVERRIDE.get(), dirs::runtime_dir()) {
+        (None, Some(dir)) => dir.join("filetransfer.sock"),
+        _ => config_dir().join("daemon.sock"),
+    }
+}
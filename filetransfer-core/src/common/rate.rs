@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// The rate is averaged over this much time, long enough to smooth out bursts
+const WINDOW: Duration = Duration::from_secs(5);
+// Progress is reported per chunk, a sample is kept at most this often
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+// Moving window estimate of bytes per second
+#[derive(Clone, Default)]
+pub struct RateEstimator {
+    samples: VecDeque<(Instant, u64)>,
+    latest: Option<(Instant, u64)>,
+}
+
+impl RateEstimator {
+    // `bytes` is the total transferred so far
+    pub fn record(&mut self, bytes: u64) {
+        self.record_at(Instant::now(), bytes);
+    }
+
+    fn record_at(&mut self, now: Instant, bytes: u64) {
+        self.latest = Some((now, bytes));
+
+        if self.samples.back().is_none_or(|(at, _)| now.duration_since(*at) >= SAMPLE_INTERVAL) {
+            self.samples.push_back((now, bytes));
+        }
+
+        // Keep one sample older than the window so the window is always covered
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) > WINDOW {
+            self.samples.pop_front();
+        }
+    }
+
+    // None until there is enough to measure
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        self.bytes_per_sec_at(Instant::now())
+    }
+
+    fn bytes_per_sec_at(&self, now: Instant) -> Option<f64> {
+        let (first_at, first_bytes) = *self.samples.front()?;
+        let (last_at, last_bytes) = self.latest?;
+
+        let elapsed = last_at.duration_since(first_at).as_secs_f64();
+        if elapsed < SAMPLE_INTERVAL.as_secs_f64() {
+            return None;
+        }
+
+        // Stalled transfers slow down the estimate until the window has passed
+        let elapsed = elapsed + now.duration_since(last_at).as_secs_f64();
+        Some(last_bytes.saturating_sub(first_bytes) as f64 / elapsed)
+    }
+}
+
+pub fn eta(remaining: u64, bytes_per_sec: f64) -> Option<Duration> {
+    if bytes_per_sec <= 0.0 {
+        return None;
+    }
+    Some(Duration::from_secs_f64(remaining as f64 / bytes_per_sec))
+}
+
+// E.g. "1h 02m", "3m 20s" or "12s"
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 3600 {
+        format!("{}h {:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+// E.g. "12.5 MB/s"
+pub fn format_rate(bytes_per_sec: f64) -> String {
+    format!("{}/s", super::transfer_state::format_bytes(bytes_per_sec as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn needs_some_elapsed_time() {
+        let start = Instant::now();
+        let mut rate = RateEstimator::default();
+        assert_eq!(rate.bytes_per_sec_at(start), None);
+
+        rate.record_at(start, 0);
+        rate.record_at(start, 4096);
+        assert_eq!(rate.bytes_per_sec_at(start), None);
+    }
+
+    #[test]
+    fn smooths_bursts_and_stalls() {
+        let start = Instant::now();
+        let mut rate = RateEstimator::default();
+        rate.record_at(start, 0);
+        rate.record_at(start + SECOND, 1000);
+        // 10 KB in the last second, averaged with the slow one before
+        rate.record_at(start + 2 * SECOND, 11_000);
+        assert_eq!(rate.bytes_per_sec_at(start + 2 * SECOND), Some(5500.0));
+        // Nothing for two seconds
+        assert_eq!(rate.bytes_per_sec_at(start + 4 * SECOND), Some(2750.0));
+    }
+
+    #[test]
+    fn forgets_samples_older_than_the_window() {
+        let start = Instant::now();
+        let mut rate = RateEstimator::default();
+        // Slow at first, then steady at 1 KB/s for longer than the window
+        rate.record_at(start, 0);
+        for secs in 1..=20 {
+            rate.record_at(start + secs * SECOND, 100 + (secs as u64 - 1) * 1000);
+        }
+        assert!(rate.samples.len() <= WINDOW.as_secs() as usize + 2);
+        assert_eq!(rate.bytes_per_sec_at(start + 20 * SECOND), Some(1000.0));
+    }
+
+    #[test]
+    fn eta_needs_a_rate() {
+        assert_eq!(eta(1000, 0.0), None);
+        assert_eq!(eta(1000, 100.0), Some(Duration::from_secs(10)));
+        assert_eq!(eta(0, 100.0), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn formats_durations_and_rates() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(59_900)), "59s");
+        assert_eq!(format_duration(Duration::from_secs(200)), "3m 20s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+        assert_eq!(format_rate(0.0), "0 B/s");
+        assert_eq!(format_rate(12_500_000.0), "12.5 MB/s");
+    }
+}
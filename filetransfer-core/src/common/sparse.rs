@@ -0,0 +1,153 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/*
+Before the file content the sender sends the map of the data extents:
+
+count   u32 LE
+offset  u64 LE  \
+length  u64 LE  / repeated count times, sorted and not overlapping
+
+followed by the bytes of every extent. Everything outside the extents is a hole
+and is recreated as such by the receiver.
+*/
+
+const MAX_EXTENTS: u32 = 1024 * 1024;
+
+// Falls back to a single extent when the platform or the filesystem can't tell where the holes are
+pub fn data_extents(file: &File, size: u64) -> Vec<(u64, u64)> {
+    if size == 0 {
+        return Vec::new();
+    }
+
+    #[cfg(target_os = "linux")]
+    if let Some(extents) = seek_extents(file, size) {
+        return extents;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    let _ = file;
+
+    vec![(0, size)]
+}
+
+#[cfg(target_os = "linux")]
+fn seek_extents(file: &File, size: u64) -> Option<Vec<(u64, u64)>> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = file.as_raw_fd();
+    let mut extents = Vec::new();
+    let mut pos: u64 = 0;
+
+    while pos < size {
+        let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            // ENXIO: only a hole until the end of the file
+            if io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) {
+                break;
+            }
+            return None;
+        }
+
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return None;
+        }
+
+        let (data, hole) = (data as u64, (hole as u64).min(size));
+        if hole <= data {
+            break;
+        }
+        extents.push((data, hole - data));
+        pos = hole;
+    }
+
+    if extents.len() > MAX_EXTENTS as usize {
+        return None;
+    }
+
+    Some(extents)
+}
+
+pub fn write_extent_map<W: Write>(writer: &mut W, extents: &[(u64, u64)]) -> io::Result<()> {
+    let mut map = Vec::with_capacity(4 + extents.len() * 16);
+    map.extend_from_slice(&(extents.len() as u32).to_le_bytes());
+    for (offset, len) in extents {
+        map.extend_from_slice(&offset.to_le_bytes());
+        map.extend_from_slice(&len.to_le_bytes());
+    }
+    writer.write_all(&map)
+}
+
+pub fn read_extent_map<R: Read>(reader: &mut R, size: u64) -> io::Result<Vec<(u64, u64)>> {
+    let mut u32_buf = [0u8; 4];
+    let mut u64_buf = [0u8; 8];
+
+    reader.read_exact(&mut u32_buf)?;
+    let count = u32::from_le_bytes(u32_buf);
+    if count > MAX_EXTENTS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Too many extents"));
+    }
+
+    let mut extents = Vec::with_capacity(count as usize);
+    let mut end = 0;
+    for _ in 0..count {
+        reader.read_exact(&mut u64_buf)?;
+        let offset = u64::from_le_bytes(u64_buf);
+        reader.read_exact(&mut u64_buf)?;
+        let len = u64::from_le_bytes(u64_buf);
+
+        if offset < end || offset.checked_add(len).is_none_or(|extent_end| extent_end > size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid extent map"));
+        }
+        end = offset + len;
+        extents.push((offset, len));
+    }
+
+    Ok(extents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Seek, SeekFrom};
+
+    #[test]
+    fn extent_map_survives_the_wire() {
+        let extents = vec![(0, 10), (4096, 100), (1 << 20, 1)];
+        let mut wire = Vec::new();
+        write_extent_map(&mut wire, &extents).unwrap();
+        assert_eq!(read_extent_map(&mut Cursor::new(&wire), (1 << 20) + 1).unwrap(), extents);
+    }
+
+    #[test]
+    fn refuses_overlapping_or_oversized_extents() {
+        for (extents, size) in [(vec![(0, 100), (50, 10)], 1000), (vec![(900, 200)], 1000), (vec![(u64::MAX, 2)], u64::MAX)] {
+            let mut wire = Vec::new();
+            write_extent_map(&mut wire, &extents).unwrap();
+            let error = read_extent_map(&mut Cursor::new(&wire), size).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn extents_cover_the_data_of_a_sparse_file() {
+        let path = std::env::temp_dir().join(format!("ft-sparse-{}", std::process::id()));
+        let mut file = File::options().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+        let size = 8 * 1024 * 1024;
+        file.set_len(size).unwrap();
+        file.seek(SeekFrom::Start(4 * 1024 * 1024)).unwrap();
+        file.write_all(b"data in the middle").unwrap();
+        file.sync_all().unwrap();
+
+        // Filesystems without hole support report a single extent, either way the data is inside one
+        let extents = data_extents(&file, size);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(extents.iter().all(|(offset, len)| offset + len <= size));
+        assert!(extents.windows(2).all(|pair| pair[0].0 + pair[0].1 <= pair[1].0));
+        let data = 4 * 1024 * 1024..4 * 1024 * 1024 + 18;
+        assert!(extents.iter().any(|(offset, len)| *offset <= data.start && data.end <= offset + len));
+        assert!(data_extents(&file, 0).is_empty());
+    }
+}
@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::rate::{self, RateEstimator};
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum TransferType {
+    Sending,
+    Receiving,
+    ComputingHash,
+    VerifyingHash,
+    CompletelyReceived,
+    CompletelySent,
+    Error(TransferError),
+}
+
+// Why a transfer ended in TransferType::Error
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TransferError {
+    // Nothing listening on the peer, or it refused the connection
+    Refused,
+    // The user on the other side, or its settings, said no
+    Rejected,
+    Io(String),
+    Timeout,
+    HashMismatch,
+}
+
+impl std::fmt::Display for TransferError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TransferError::Refused => write!(f, "connection refused"),
+            TransferError::Rejected => write!(f, "rejected"),
+            TransferError::Io(message) => write!(f, "I/O error: {}", message),
+            TransferError::Timeout => write!(f, "timed out"),
+            TransferError::HashMismatch => write!(f, "hash mismatch"),
+        }
+    }
+}
+
+impl From<std::io::Error> for TransferError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::ConnectionRefused => TransferError::Refused,
+            std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => TransferError::Timeout,
+            _ => TransferError::Io(e.to_string()),
+        }
+    }
+}
+
+impl TransferType {
+    pub fn label(&self) -> &'static str {
+        match self {
+            TransferType::Sending => "Sending",
+            TransferType::Receiving => "Receiving",
+            TransferType::ComputingHash => "Computing Hash",
+            TransferType::VerifyingHash => "Verifying Hash",
+            TransferType::CompletelyReceived => "Completed Type: Receive",
+            TransferType::CompletelySent => "Completed Type: Send",
+            TransferType::Error(_) => "Error",
+        }
+    }
+
+    // Nothing changes after one of these
+    pub fn is_finished(&self) -> bool {
+        matches!(self, TransferType::CompletelyReceived | TransferType::CompletelySent | TransferType::Error(_))
+    }
+}
+
+// One line of the per-transfer log shown in the GUI
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransferEvent {
+    pub at: SystemTime,
+    pub message: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransferState {
+    pub ttype: TransferType,
+    pub original_filepath: String,
+    pub dest_filepath: String,
+    pub percentage: f32,
+    pub peer: std::net::SocketAddr,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub started_at: SystemTime,
+    // Set when the transfer completes or fails
+    pub ended_at: Option<SystemTime>,
+    pub events: Vec<TransferEvent>,
+    // The request this transfer is part of, for the batch totals
+    pub batch: Option<u32>,
+    // SHA-256 of the content when the side reporting it knows it, for the history
+    #[serde(default)]
+    pub hash: Option<[u8; 32]>,
+    // Local to whoever watches the transfer, rebuilt from the progress updates
+    #[serde(skip)]
+    pub rate: RateEstimator,
+}
+
+impl TransferState {
+    pub fn new(ttype: TransferType, original_filepath: String, dest_filepath: String, peer: std::net::SocketAddr, bytes_total: u64) -> Self {
+        let mut state = TransferState {
+            ttype,
+            original_filepath,
+            dest_filepath,
+            percentage: 0.0,
+            peer,
+            bytes_done: 0,
+            bytes_total,
+            started_at: SystemTime::now(),
+            ended_at: None,
+            events: Vec::new(),
+            batch: None,
+            hash: None,
+            rate: RateEstimator::default(),
+        };
+        state.log(format!("Started, {} to transfer", format_bytes(bytes_total)));
+        state.log_type();
+        state
+    }
+
+    pub fn log(&mut self, message: impl Into<String>) {
+        self.events.push(TransferEvent { at: SystemTime::now(), message: message.into() });
+    }
+
+    // Logs the change, and the end of the transfer for the final states
+    pub fn set_type(&mut self, ttype: TransferType) {
+        let changed = std::mem::discriminant(&ttype) != std::mem::discriminant(&self.ttype);
+        self.ttype = ttype;
+        if changed {
+            self.log_type();
+        }
+    }
+
+    fn log_type(&mut self) {
+        let message = match self.error_text() {
+            Some(error) => format!("Failed: {}", error),
+            None => self.ttype.label().to_string(),
+        };
+        self.log(message);
+
+        if self.ttype.is_finished() {
+            self.ended_at = Some(SystemTime::now());
+            if self.error().is_none() {
+                self.bytes_done = self.bytes_total;
+                self.percentage = 100.0;
+            }
+        }
+    }
+
+    pub fn set_progress(&mut self, bytes_done: u64) {
+        self.bytes_done = bytes_done;
+        self.rate.record(bytes_done);
+        self.percentage = if self.bytes_total == 0 {
+            100.0
+        } else {
+            (bytes_done as f32 / self.bytes_total as f32) * 100.0
+        };
+    }
+
+    pub fn error(&self) -> Option<&TransferError> {
+        match &self.ttype {
+            TransferType::Error(reason) => Some(reason),
+            _ => None,
+        }
+    }
+
+    // E.g. "hash mismatch after 3.2 GB"
+    pub fn error_text(&self) -> Option<String> {
+        self.error().map(|reason| {
+            if self.bytes_done == 0 {
+                reason.to_string()
+            } else {
+                format!("{} after {}", reason, format_bytes(self.bytes_done))
+            }
+        })
+    }
+
+    // None once the transfer is over
+    pub fn bytes_per_sec(&self) -> Option<f64> {
+        if self.ttype.is_finished() {
+            return None;
+        }
+        self.rate.bytes_per_sec()
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        rate::eta(self.bytes_total.saturating_sub(self.bytes_done), self.bytes_per_sec()?)
+    }
+
+    // Until now for running transfers
+    pub fn duration(&self) -> Duration {
+        self.ended_at.unwrap_or_else(SystemTime::now).duration_since(self.started_at).unwrap_or_default()
+    }
+}
+
+// Totals over the transfers of one request, running in parallel
+#[derive(Default)]
+pub struct BatchProgress {
+    pub transfers: usize,
+    pub finished: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub bytes_per_sec: f64,
+}
+
+impl BatchProgress {
+    pub fn add(&mut self, state: &TransferState) {
+        self.transfers += 1;
+        if state.ttype.is_finished() {
+            self.finished += 1;
+        }
+        self.bytes_done += state.bytes_done;
+        // The rest of a failed transfer will never come
+        self.bytes_total += if state.error().is_some() { state.bytes_done } else { state.bytes_total };
+        self.bytes_per_sec += state.bytes_per_sec().unwrap_or(0.0);
+    }
+
+    pub fn eta(&self) -> Option<Duration> {
+        if self.finished == self.transfers {
+            return None;
+        }
+        rate::eta(self.bytes_total.saturating_sub(self.bytes_done), self.bytes_per_sec)
+    }
+}
+
+pub fn batches<'a>(states: impl Iterator<Item = &'a TransferState>) -> BTreeMap<u32, BatchProgress> {
+    let mut batches: BTreeMap<u32, BatchProgress> = BTreeMap::new();
+    for state in states {
+        let Some(batch) = state.batch else {
+            continue;
+        };
+
+        batches.entry(batch).or_default().add(state);
+    }
+    batches
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+impl Default for TransferState {
+    fn default() -> Self {
+        TransferState {
+            ttype: TransferType::Sending,
+            original_filepath: String::new(),
+            dest_filepath: String::new(),
+            percentage: 0.0,
+            peer: std::net::SocketAddr::new(
+                std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
+                0,
+            ),
+            bytes_done: 0,
+            bytes_total: 0,
+            started_at: SystemTime::now(),
+            ended_at: None,
+            events: Vec::new(),
+            batch: None,
+            hash: None,
+            rate: RateEstimator::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(batch: Option<u32>, bytes_total: u64) -> TransferState {
+        let mut state = TransferState::new(TransferType::Receiving, String::new(), String::from("/tmp/a"), "192.168.1.20:40000".parse().unwrap(), bytes_total);
+        state.batch = batch;
+        state
+    }
+
+    #[test]
+    fn has_no_rate_before_any_time_passed() {
+        let mut state = transfer(None, 1000);
+        assert!(state.eta().is_none());
+        state.set_progress(250);
+        state.set_progress(500);
+        assert_eq!((state.bytes_done, state.percentage), (500, 50.0));
+        assert!(state.bytes_per_sec().is_none() && state.eta().is_none());
+    }
+
+    #[test]
+    fn handles_an_unknown_total() {
+        let mut state = transfer(Some(1), 0);
+        state.set_progress(0);
+        assert_eq!(state.percentage, 100.0);
+
+        let batches = batches([&state].into_iter());
+        let batch = &batches[&1];
+        assert_eq!((batch.bytes_done, batch.bytes_total, batch.bytes_per_sec), (0, 0, 0.0));
+        assert!(batch.eta().is_none());
+    }
+
+    #[test]
+    fn counts_finished_transfers_whole_and_failed_ones_up_to_where_they_stopped() {
+        let mut sent = transfer(Some(1), 1000);
+        sent.set_progress(400);
+        sent.set_type(TransferType::CompletelyReceived);
+        assert_eq!((sent.bytes_done, sent.percentage), (1000, 100.0));
+        assert!(sent.ended_at.is_some() && sent.bytes_per_sec().is_none() && sent.eta().is_none());
+
+        let mut failed = transfer(Some(1), 5000);
+        failed.set_progress(1500);
+        failed.set_type(TransferType::Error(TransferError::Timeout));
+        assert_eq!(failed.bytes_done, 1500);
+        assert_eq!(failed.error_text().as_deref(), Some("timed out after 1.5 KB"));
+
+        let running = transfer(Some(2), 300);
+        let outside = transfer(None, 700);
+        let batches = batches([&sent, &failed, &running, &outside].into_iter());
+
+        assert_eq!(batches.keys().copied().collect::<Vec<_>>(), vec![1, 2]);
+        let finished = &batches[&1];
+        assert_eq!((finished.transfers, finished.finished, finished.bytes_done, finished.bytes_total), (2, 2, 2500, 2500));
+        assert!(finished.eta().is_none());
+        assert_eq!((batches[&2].finished, batches[&2].bytes_total), (0, 300));
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(999), "999 B");
+        assert_eq!(format_bytes(1500), "1.5 KB");
+        assert_eq!(format_bytes(3_200_000_000), "3.2 GB");
+    }
+}
@@ -0,0 +1,72 @@
+// Extended attributes are only transferred between Linux machines.
+// POSIX ACLs are stored as system.posix_acl_* attributes, so they travel with them.
+
+use std::path::Path;
+
+pub type Xattrs = Vec<(String, Vec<u8>)>;
+
+// Anything else (trusted.*, security.capability, ...) is never read nor applied
+const ALLOWED_PREFIXES: [&str; 4] = [
+    "user.",
+    "security.selinux",
+    "system.posix_acl_access",
+    "system.posix_acl_default",
+];
+
+// They decide who may read, write or run the file like the permission bits do, an access ACL even rewrites them
+const PERMISSION_PREFIXES: [&str; 3] = [
+    "security.selinux",
+    "system.posix_acl_access",
+    "system.posix_acl_default",
+];
+
+pub fn is_allowed(name: &str) -> bool {
+    ALLOWED_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+pub fn is_permission(name: &str) -> bool {
+    PERMISSION_PREFIXES.iter().any(|prefix| name.starts_with(prefix))
+}
+
+// Symlinks are not followed
+#[cfg(target_os = "linux")]
+pub fn read(path: &str) -> Xattrs {
+    let mut attrs = Vec::new();
+    let Ok(names) = xattr::list(path) else {
+        return attrs;
+    };
+
+    for name in names {
+        let name = name.to_string_lossy().into_owned();
+        if !is_allowed(&name) {
+            continue;
+        }
+        if let Ok(Some(value)) = xattr::get(path, &name) {
+            attrs.push((name, value));
+        }
+    }
+    attrs
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read(_path: &str) -> Xattrs {
+    Vec::new()
+}
+
+// Applies what the filesystem and our privileges allow, the rest is skipped.
+// Without `apply_permissions` the ACLs and the SELinux label are left out, like the permission bits (see common::metadata)
+#[cfg(target_os = "linux")]
+pub fn apply(path: impl AsRef<Path>, attrs: &Xattrs, apply_permissions: bool) {
+    let path = path.as_ref();
+    for (name, value) in attrs {
+        if !is_allowed(name) || (!apply_permissions && is_permission(name)) {
+            continue;
+        }
+        if let Err(e) = xattr::set(path, name, value) {
+            eprintln!("Cannot set {} on {}: {}", name, path.display(), e);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn apply(_path: impl AsRef<Path>, _attrs: &Xattrs, _apply_permissions: bool) {}
@@ -0,0 +1,154 @@
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+// Never connected, there is no daemon on these platforms
+#[cfg(not(unix))]
+use std::net::TcpStream as UnixStream;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::daemon::{AnswerParams, NodeStatus, PendingRequest, RpcError, RpcRequest, RpcResponse, SendParams, TransferEntry};
+use crate::engine::events::Event;
+use crate::networking::client::PingResponse;
+
+#[derive(Debug)]
+pub enum DaemonError {
+    Io(io::Error),
+    Rpc(RpcError),
+}
+
+impl std::fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DaemonError::Io(e) => write!(f, "{}", e),
+            DaemonError::Rpc(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for DaemonError {
+    fn from(e: io::Error) -> Self {
+        DaemonError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DaemonError {
+    fn from(e: serde_json::Error) -> Self {
+        DaemonError::Io(e.into())
+    }
+}
+
+struct Connection {
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
+    next_id: u64,
+}
+
+// Calls one at a time, see daemon for the methods
+pub struct DaemonClient {
+    connection: Mutex<Connection>,
+}
+
+impl DaemonClient {
+    // Fails when no daemon is running
+    #[cfg(unix)]
+    pub fn connect(path: &Path) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(DaemonClient { connection: Mutex::new(Connection { writer, reader, next_id: 1 }) })
+    }
+
+    #[cfg(not(unix))]
+    pub fn connect(_path: &Path) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, DaemonError> {
+        let mut connection = self.connection.lock().unwrap();
+        let id = connection.next_id;
+        connection.next_id += 1;
+
+        let request = RpcRequest { jsonrpc: String::from("2.0"), id: Some(json!(id)), method: method.to_string(), params };
+        let mut line = serde_json::to_string(&request)?;
+        line.push('\n');
+        connection.writer.write_all(line.as_bytes())?;
+
+        loop {
+            let mut line = String::new();
+            if connection.reader.read_line(&mut line)? == 0 {
+                return Err(DaemonError::Io(io::ErrorKind::UnexpectedEof.into()));
+            }
+            let response: RpcResponse = serde_json::from_str(&line)?;
+            if response.id != json!(id) {
+                continue;
+            }
+            if let Some(error) = response.error {
+                return Err(DaemonError::Rpc(error));
+            }
+            return Ok(serde_json::from_value(response.result.unwrap_or(Value::Null))?);
+        }
+    }
+
+    pub fn status(&self) -> Result<NodeStatus, DaemonError> {
+        self.call("status", Value::Null)
+    }
+
+    pub fn peers(&self) -> Result<Vec<PingResponse>, DaemonError> {
+        self.call("peers", Value::Null)
+    }
+
+    pub fn requests(&self) -> Result<Vec<PendingRequest>, DaemonError> {
+        self.call("requests", Value::Null)
+    }
+
+    pub fn transfers(&self) -> Result<Vec<TransferEntry>, DaemonError> {
+        self.call("transfers", Value::Null)
+    }
+
+    // All the files of the request when `files` is None, an empty list rejects it
+    pub fn accept_request(&self, id: u32, files: Option<Vec<String>>) -> Result<(), DaemonError> {
+        self.call("accept", serde_json::to_value(AnswerParams { id, files })?)
+    }
+
+    pub fn reject_request(&self, id: u32) -> Result<(), DaemonError> {
+        self.call("reject", serde_json::to_value(AnswerParams { id, files: None })?)
+    }
+
+    // `peer` as in Node::find_peer, `files` must be absolute
+    pub fn send_files(&self, peer: &str, files: Vec<String>) -> Result<(), DaemonError> {
+        self.call("send", serde_json::to_value(SendParams { peer: peer.to_string(), files })?)
+    }
+}
+
+// On a connection of its own, like engine::Engine::subscribe. The receiver is closed when the daemon exits
+pub fn subscribe(path: &Path) -> Result<Receiver<Event>, DaemonError> {
+    let client = DaemonClient::connect(path)?;
+    client.call::<Value>("subscribe", Value::Null)?;
+    let Connection { reader, .. } = client.connection.into_inner().unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let Ok(notification) = serde_json::from_str::<RpcRequest>(&line) else {
+                continue;
+            };
+            let Ok(event) = serde_json::from_value::<Event>(notification.params) else {
+                eprintln!("Cannot read the event from the daemon: {}", line);
+                continue;
+            };
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    Ok(receiver)
+}
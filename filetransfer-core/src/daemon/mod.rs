@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::common::transfer_state::TransferState;
+use crate::networking::ports::Ports;
+use crate::networking::supervisor::ServiceState;
+use crate::networking::server::RequestData;
+
+/*
+Control socket of a node running without a window (`ft daemon`), the CLI and the GUI attach to it
+to answer its requests and start sends. JSON-RPC 2.0, one message per line, over a Unix socket
+only the user can open (see common::paths::daemon_socket).
+
+status                   identity, ports, services and receive directory of the daemon's node
+peers                    discovered peers
+requests                 requests waiting for an answer
+transfers                every transfer so far
+accept {id, files?}      accepts these files of the request, all of them without `files`
+reject {id}
+send {peer, files}       peer by name, device ID or address, files as absolute paths
+subscribe                the current requests and transfers, then every change, as `event` notifications
+                         whose params are an engine::events::Event. The connection only gets events afterwards
+
+{"jsonrpc":"2.0","id":1,"method":"accept","params":{"id":12}}
+{"jsonrpc":"2.0","id":1,"result":null}
+*/
+
+pub mod client;
+#[cfg(unix)]
+pub mod server;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const UNKNOWN_REQUEST: i64 = 1;
+pub const PEER_NOT_FOUND: i64 = 2;
+pub const BAD_FILE: i64 = 3;
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcRequest {
+    pub jsonrpc: String,
+    // Absent for notifications, they get no response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    pub method: String,
+    #[serde(default)]
+    pub params: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RpcResponse {
+    pub jsonrpc: String,
+    pub id: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (error {})", self.message, self.code)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AnswerParams {
+    pub id: u32,
+    #[serde(default)]
+    pub files: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendParams {
+    pub peer: String,
+    pub files: Vec<String>,
+}
+
+// Result of `status`
+#[derive(Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub name: String,
+    pub device_id: String,
+    pub ports: Ports,
+    pub mdns: bool,
+    pub services: BTreeMap<String, ServiceState>,
+    pub receive_dir: PathBuf,
+    pub peers: usize,
+}
+
+// Results of `requests` and `transfers`
+#[derive(Serialize, Deserialize)]
+pub struct PendingRequest {
+    pub id: u32,
+    #[serde(flatten)]
+    pub request: RequestData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferEntry {
+    pub id: u32,
+    #[serde(flatten)]
+    pub state: TransferState,
+}
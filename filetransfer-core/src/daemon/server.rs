@@ -0,0 +1,216 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::Node;
+use crate::daemon::{self, AnswerParams, NodeStatus, PendingRequest, RpcError, RpcRequest, RpcResponse, SendParams, TransferEntry};
+use crate::engine::{self, events::Event};
+
+// Blocks forever, one thread per connection
+pub fn serve(node: Arc<Node>, path: &Path) -> io::Result<()> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a daemon is already listening on {}", path.display())));
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = fs::remove_file(path);
+
+    let listener = bind_private(path)?;
+    eprintln!("Daemon listening on {}", path.display());
+
+    // The daemon's own view of the requests and transfers, for the list methods
+    let state = Arc::new(Mutex::new(engine::State::default()));
+    thread::spawn({
+        let state = state.clone();
+        let events = node.subscribe_events();
+        move || {
+            for event in events {
+                let mut state = state.lock().unwrap();
+                state.apply(&event);
+                if matches!(&event, Event::TransferAdded { .. } | Event::TransferChanged { .. }) {
+                    state.prune_finished(engine::MAX_FINISHED_TRANSFERS);
+                }
+            }
+        }
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let node = node.clone();
+                let state = state.clone();
+                thread::spawn(move || {
+                    if let Err(e) = connection(stream, &node, &state) {
+                        eprintln!("Control connection closed: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("Cannot accept a control connection: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+// Whoever can open the socket can accept files in our name. It is bound in a directory only we can enter
+// and moved in place once it is 0600, so it is never reachable with the permissions of the umask
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    // Only the directories created here, an existing config dir keeps its permissions
+    fs::DirBuilder::new().recursive(true).mode(0o700).create(parent)?;
+
+    let private = parent.join(format!(".daemon-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&private);
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+
+    let staged = private.join("socket");
+    let listener = UnixListener::bind(&staged).and_then(|listener| {
+        fs::set_permissions(&staged, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&staged, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_dir_all(&private);
+    listener
+}
+
+fn connection(stream: UnixStream, node: &Node, state: &Mutex<engine::State>) -> io::Result<()> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let request = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(request) => request,
+            Err(e) => {
+                write_message(&mut writer, &response(Value::Null, Err(RpcError::new(daemon::PARSE_ERROR, e.to_string()))))?;
+                continue;
+            }
+        };
+
+        if request.method == "subscribe" {
+            if let Some(id) = request.id {
+                write_message(&mut writer, &response(id, Ok(Value::Null)))?;
+            }
+            return subscribe(writer, node);
+        }
+
+        let result = handle(&request, node, state);
+        if let Some(id) = request.id {
+            write_message(&mut writer, &response(id, result))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Until the client goes away
+fn subscribe(mut writer: UnixStream, node: &Node) -> io::Result<()> {
+    for event in node.subscribe_events() {
+        let notification = json!({ "jsonrpc": "2.0", "method": "event", "params": event });
+        write_message(&mut writer, &notification)?;
+    }
+    Ok(())
+}
+
+fn handle(request: &RpcRequest, node: &Node, state: &Mutex<engine::State>) -> Result<Value, RpcError> {
+    match request.method.as_str() {
+        "status" => {
+            let identity = node.identity.lock().unwrap().clone();
+            to_value(NodeStatus {
+                name: identity.display_name,
+                device_id: identity.device_id,
+                ports: node.ports,
+                mdns: node.mdns.is_some(),
+                services: node.services.lock().unwrap().iter().map(|(name, state)| (name.to_string(), state.clone())).collect(),
+                receive_dir: node.receiver_settings.lock().unwrap().receive_dir.clone(),
+                peers: node.peers().len(),
+            })
+        }
+        "peers" => to_value(node.peers()),
+        "requests" => {
+            let state = state.lock().unwrap();
+            let requests = state.requests.iter()
+                .map(|(id, request)| PendingRequest { id: *id, request: request.clone() })
+                .collect::<Vec<_>>();
+            to_value(requests)
+        }
+        "transfers" => {
+            let state = state.lock().unwrap();
+            let mut transfers = state.transfers.iter()
+                .map(|(id, transfer)| TransferEntry { id: *id, state: transfer.clone() })
+                .collect::<Vec<_>>();
+            transfers.sort_by_key(|transfer| transfer.id);
+            to_value(transfers)
+        }
+        "accept" => {
+            let params: AnswerParams = params(request)?;
+            let files = match params.files {
+                Some(files) => files,
+                None => state.lock().unwrap().requests.get(&params.id)
+                    .map(|request| request.files.iter().map(|f| f.name.clone()).collect())
+                    .unwrap_or_default(),
+            };
+            answer(node, state, params.id, files)
+        }
+        "reject" => {
+            let params: AnswerParams = params(request)?;
+            answer(node, state, params.id, Vec::new())
+        }
+        "send" => {
+            let params: SendParams = params(request)?;
+            // Relative paths would be resolved against the daemon's directory, not the caller's
+            // Directories and special files are not supported by the protocol, symlinks are sent as symlinks
+            for file in params.files.iter() {
+                let sendable = fs::symlink_metadata(file).is_ok_and(|metadata| metadata.is_file() || metadata.is_symlink());
+                if !Path::new(file).is_absolute() || !sendable {
+                    return Err(RpcError::new(daemon::BAD_FILE, format!("{} is not an absolute path to a file or a symlink", file)));
+                }
+            }
+            let peer = node.find_peer(&params.peer)
+                .ok_or_else(|| RpcError::new(daemon::PEER_NOT_FOUND, format!("peer {} not found", params.peer)))?;
+            node.send_files(peer, params.files);
+            Ok(Value::Null)
+        }
+        method => Err(RpcError::new(daemon::METHOD_NOT_FOUND, format!("unknown method {}", method))),
+    }
+}
+
+fn answer(node: &Node, state: &Mutex<engine::State>, id: u32, files: Vec<String>) -> Result<Value, RpcError> {
+    if !state.lock().unwrap().requests.contains_key(&id) {
+        return Err(RpcError::new(daemon::UNKNOWN_REQUEST, format!("no pending request {}", id)));
+    }
+    node.accept_request(id, files);
+    Ok(Value::Null)
+}
+
+fn params<T: DeserializeOwned>(request: &RpcRequest) -> Result<T, RpcError> {
+    serde_json::from_value(request.params.clone()).map_err(|e| RpcError::new(daemon::INVALID_PARAMS, e.to_string()))
+}
+
+fn to_value(value: impl serde::Serialize) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(daemon::INTERNAL_ERROR, e.to_string()))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> RpcResponse {
+    let (result, error) = match result {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    RpcResponse { jsonrpc: String::from("2.0"), id, result, error }
+}
+
+fn write_message(writer: &mut UnixStream, message: &impl serde::Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+    writer.write_all(line.as_bytes())
+}
@@ -0,0 +1,120 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::transfer_state::{TransferState, TransferType};
+use crate::networking::client::PingResponse;
+use crate::networking::server::RequestData;
+
+// Transfers report every chunk, subscribers get at most this many updates
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Everything subscribers learn about requests and transfers, in order
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Event {
+    RequestReceived { id: u32, request: RequestData },
+    // Answered, nothing left to decide. No files means it was rejected
+    RequestClosed { id: u32, accepted_files: Vec<String> },
+    TransferAdded { id: u32, state: TransferState },
+    TransferProgress { id: u32, bytes_done: u64 },
+    TransferChanged { id: u32, ttype: TransferType },
+    TransferLog { id: u32, message: String },
+    // Ends the events replayed to a new subscriber, what was there when it subscribed. The next ones are new
+    SnapshotEnd,
+}
+
+pub enum Command {
+    // An empty list rejects the request
+    AnswerRequest { id: u32, accepted_files: Vec<String> },
+    // Boxed, the other commands and inputs are much smaller
+    SendFiles { peer: Box<PingResponse>, files: Vec<String> },
+}
+
+// What the engine thread reacts to, see engine::run
+pub enum Input {
+    Event(Event),
+    Command(Command),
+    // The server thread waits on `reply` for the accepted files
+    Request { id: u32, request: RequestData, reply: Sender<Vec<String>> },
+    Subscribe(Sender<Event>),
+}
+
+// Handed to the server and client threads, sending never blocks
+#[derive(Clone)]
+pub struct EventSender {
+    input: Sender<Input>,
+}
+
+impl EventSender {
+    pub fn new(input: Sender<Input>) -> Self {
+        EventSender { input }
+    }
+
+    // The engine only stops with the process, a failed send has nobody to tell
+    pub fn send(&self, event: Event) {
+        let _ = self.input.send(Input::Event(event));
+    }
+
+    pub fn add(&self, id: u32, state: TransferState) {
+        self.send(Event::TransferAdded { id, state });
+    }
+
+    pub fn set_type(&self, id: u32, ttype: TransferType) {
+        self.send(Event::TransferChanged { id, ttype });
+    }
+
+    pub fn log(&self, id: u32, message: impl Into<String>) {
+        self.send(Event::TransferLog { id, message: message.into() });
+    }
+
+    // Blocks on the returned receiver until the request is answered, it fails if the engine is gone
+    pub fn request(&self, id: u32, request: RequestData) -> Receiver<Vec<String>> {
+        let (reply, answer) = mpsc::channel();
+        let _ = self.input.send(Input::Request { id, request, reply });
+        answer
+    }
+
+    // The sender went away before the request was answered, it is closed as rejected
+    pub fn withdraw(&self, id: u32) {
+        let _ = self.input.send(Input::Command(Command::AnswerRequest { id, accepted_files: Vec::new() }));
+    }
+}
+
+// Sends the progress of one transfer, throttled to PROGRESS_INTERVAL
+pub struct ProgressReporter {
+    events: EventSender,
+    id: u32,
+    last_sent: Option<Instant>,
+    pending: Option<u64>,
+}
+
+impl ProgressReporter {
+    pub fn new(events: EventSender, id: u32) -> Self {
+        ProgressReporter {
+            events,
+            id,
+            last_sent: None,
+            pending: None,
+        }
+    }
+
+    pub fn report(&mut self, bytes_done: u64) {
+        if self.last_sent.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+            self.pending = Some(bytes_done);
+            return;
+        }
+
+        self.events.send(Event::TransferProgress { id: self.id, bytes_done });
+        self.last_sent = Some(Instant::now());
+        self.pending = None;
+    }
+
+    // Sends the last value held back, call it when the data is over
+    pub fn flush(&mut self) {
+        if let Some(bytes_done) = self.pending.take() {
+            self.events.send(Event::TransferProgress { id: self.id, bytes_done });
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::common::hash::bytes_to_hex;
+use crate::common::manifest::EntryKind;
+use crate::common::transfer_state::{TransferError, TransferState, TransferType};
+use crate::engine::{self, State};
+use crate::engine::events::Event;
+
+/*
+The events as newline-delimited JSON, for scripts (`ft events`, `--events FILE`).
+Every line is one record, fields are only ever added within a version:
+
+{"v":1,"ts":1760000000000,"event":"request_received","request":3,"from":{...},"files":[...],"bytes_total":1024}
+
+request_received   request, from {name, address, device_id}, files [{name, size, sha256, symlink?}], bytes_total
+request_accepted   request, files (the accepted names)
+request_rejected   request
+transfer_started   transfer, request, file, dest, peer, bytes_total, status
+progress           transfer, request, bytes_done, bytes_total, percentage, bytes_per_sec?, eta_secs?
+status             transfer, request, status, while it's running
+verified           transfer, request, file, dest, peer, bytes_total, status (received or sent), duration_secs
+failed             transfer, request, file, dest, peer, bytes_done, error, reason
+log                transfer, message
+
+`ts` is in milliseconds since the Unix epoch, `request` is null for transfers outside of a request
+and the status is one of those of `status_name`. The records of what was already there when the
+subscription started (pending requests, running and finished transfers) have `"replay":true`,
+they were reported before to whoever was listening then.
+*/
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct Record {
+    pub v: u32,
+    pub ts: u64,
+    pub event: &'static str,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub replay: bool,
+    #[serde(flatten)]
+    pub fields: Value,
+}
+
+impl Record {
+    fn new(event: &'static str, fields: Value) -> Self {
+        let ts = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        Record { v: SCHEMA_VERSION, ts, event, replay: false, fields }
+    }
+}
+
+// Turns the events of one subscription into records, keeping the state the records need
+#[derive(Default)]
+pub struct JsonEvents {
+    state: State,
+    // Until Event::SnapshotEnd the events are replayed
+    live: bool,
+}
+
+impl JsonEvents {
+    pub fn new() -> Self {
+        JsonEvents::default()
+    }
+
+    pub fn records(&mut self, event: &Event) -> Vec<Record> {
+        let mut records = self.new_records(event);
+        for record in records.iter_mut() {
+            record.replay = !self.live;
+        }
+        // Once the records are built, a long subscription would otherwise keep every transfer it saw
+        if matches!(event, Event::TransferAdded { .. } | Event::TransferChanged { .. }) {
+            self.state.prune_finished(engine::MAX_FINISHED_TRANSFERS);
+        }
+        records
+    }
+
+    fn new_records(&mut self, event: &Event) -> Vec<Record> {
+        // Finished before the event, e.g. progress arriving after the end
+        let was_finished = match event {
+            Event::TransferChanged { id, .. } => self.state.transfers.get(id).is_some_and(|t| t.ttype.is_finished()),
+            _ => false,
+        };
+        self.state.apply(event);
+
+        match event {
+            Event::RequestReceived { id, request } => {
+                let files: Vec<Value> = request.files.iter().map(|file| {
+                    let mut entry = json!({ "name": file.name, "size": file.size, "sha256": bytes_to_hex(&file.hash) });
+                    if let EntryKind::Symlink(target) = &file.kind {
+                        entry["symlink"] = json!(target);
+                    }
+                    entry
+                }).collect();
+                let from = json!({ "name": request.from.name(), "address": request.from.addr.to_string(), "device_id": request.from.device_id });
+                let bytes_total: u64 = request.files.iter().map(|f| f.size).sum();
+                vec![Record::new("request_received", json!({ "request": id, "from": from, "files": files, "bytes_total": bytes_total }))]
+            }
+            Event::RequestClosed { id, accepted_files } if accepted_files.is_empty() => {
+                vec![Record::new("request_rejected", json!({ "request": id }))]
+            }
+            Event::RequestClosed { id, accepted_files } => {
+                vec![Record::new("request_accepted", json!({ "request": id, "files": accepted_files }))]
+            }
+            Event::TransferAdded { id, .. } => {
+                let Some(transfer) = self.state.transfers.get(id) else {
+                    return Vec::new();
+                };
+                let mut records = vec![Record::new("transfer_started", json!({
+                    "transfer": id,
+                    "request": transfer.batch,
+                    "file": transfer.original_filepath,
+                    "dest": transfer.dest_filepath,
+                    "peer": transfer.peer.to_string(),
+                    "bytes_total": transfer.bytes_total,
+                    "status": status_name(&transfer.ttype),
+                }))];
+                // Local copies start finished, and so do the transfers replayed to a new subscriber
+                records.extend(finished(*id, transfer));
+                records
+            }
+            Event::TransferProgress { id, bytes_done } => {
+                let Some(transfer) = self.state.transfers.get(id) else {
+                    return Vec::new();
+                };
+                vec![Record::new("progress", json!({
+                    "transfer": id,
+                    "request": transfer.batch,
+                    "bytes_done": bytes_done,
+                    "bytes_total": transfer.bytes_total,
+                    "percentage": transfer.percentage,
+                    "bytes_per_sec": transfer.bytes_per_sec(),
+                    "eta_secs": transfer.eta().map(|eta| eta.as_secs()),
+                }))]
+            }
+            Event::TransferChanged { id, ttype } => {
+                let Some(transfer) = self.state.transfers.get(id) else {
+                    return Vec::new();
+                };
+                if was_finished {
+                    Vec::new()
+                } else if ttype.is_finished() {
+                    finished(*id, transfer).into_iter().collect()
+                } else {
+                    vec![Record::new("status", json!({ "transfer": id, "request": transfer.batch, "status": status_name(ttype) }))]
+                }
+            }
+            Event::TransferLog { id, message } => {
+                vec![Record::new("log", json!({ "transfer": id, "message": message }))]
+            }
+            Event::SnapshotEnd => {
+                self.live = true;
+                Vec::new()
+            }
+        }
+    }
+}
+
+fn finished(id: u32, transfer: &TransferState) -> Option<Record> {
+    if !transfer.ttype.is_finished() {
+        return None;
+    }
+    match transfer.error() {
+        Some(error) => Some(Record::new("failed", json!({
+            "transfer": id,
+            "request": transfer.batch,
+            "file": transfer.original_filepath,
+            "dest": transfer.dest_filepath,
+            "peer": transfer.peer.to_string(),
+            "bytes_done": transfer.bytes_done,
+            "error": error_code(error),
+            "reason": transfer.error_text(),
+        }))),
+        None => Some(Record::new("verified", json!({
+            "transfer": id,
+            "request": transfer.batch,
+            "file": transfer.original_filepath,
+            "dest": transfer.dest_filepath,
+            "peer": transfer.peer.to_string(),
+            "bytes_total": transfer.bytes_total,
+            "status": status_name(&transfer.ttype),
+            "duration_secs": transfer.duration().as_secs_f64(),
+        }))),
+    }
+}
+
+// Part of the schema, unlike TransferType::label
+pub fn status_name(ttype: &TransferType) -> &'static str {
+    match ttype {
+        TransferType::Sending => "sending",
+        TransferType::Receiving => "receiving",
+        TransferType::ComputingHash => "computing_hash",
+        TransferType::VerifyingHash => "verifying_hash",
+        TransferType::CompletelyReceived => "received",
+        TransferType::CompletelySent => "sent",
+        TransferType::Error(_) => "error",
+    }
+}
+
+pub fn error_code(error: &TransferError) -> &'static str {
+    match error {
+        TransferError::Refused => "refused",
+        TransferError::Rejected => "rejected",
+        TransferError::Io(_) => "io",
+        TransferError::Timeout => "timeout",
+        TransferError::HashMismatch => "hash_mismatch",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn received(id: u32) -> Event {
+        let state = TransferState::new(TransferType::CompletelyReceived, String::new(), String::from("/tmp/a"), "192.168.1.20:40000".parse().unwrap(), 1);
+        Event::TransferAdded { id, state }
+    }
+
+    #[test]
+    fn marks_the_replayed_records() {
+        let mut json = JsonEvents::new();
+        let replayed = json.records(&received(1));
+        assert_eq!(replayed.iter().map(|r| (r.event, r.replay)).collect::<Vec<_>>(), vec![("transfer_started", true), ("verified", true)]);
+        assert!(json.records(&Event::SnapshotEnd).is_empty());
+
+        let live = json.records(&received(2));
+        assert!(live.iter().all(|record| !record.replay));
+        assert!(!serde_json::to_string(&live[0]).unwrap().contains("replay"));
+    }
+
+    #[test]
+    fn keeps_a_bounded_number_of_finished_transfers() {
+        let mut json = JsonEvents::new();
+        let count = engine::MAX_FINISHED_TRANSFERS as u32 + 10;
+        for id in 1..=count {
+            assert_eq!(json.records(&received(id)).len(), 2);
+        }
+        assert_eq!(json.state.transfers.len(), engine::MAX_FINISHED_TRANSFERS);
+    }
+}
@@ -0,0 +1,156 @@
+pub mod events;
+pub mod json;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use crate::common::transfer_state::TransferState;
+use crate::networking::client;
+use crate::networking::server::RequestData;
+use events::{Command, Event, EventSender, Input};
+
+/*
+The engine thread owns the state of every request and transfer.
+The server and client threads send it events through an EventSender,
+frontends send it commands and get the events through a subscription.
+Nothing is shared behind a lock, every subscriber keeps its own State.
+*/
+#[derive(Clone)]
+pub struct Engine {
+    input: Sender<Input>,
+}
+
+impl Engine {
+    // `on_event` sees every event on the engine thread, before any subscriber does
+    pub fn start(on_event: impl FnMut(&Event) + Send + 'static) -> Self {
+        let (input, receiver) = mpsc::channel();
+        let events = EventSender::new(input.clone());
+        thread::spawn(move || run(receiver, events, on_event));
+        Engine { input }
+    }
+
+    pub fn events(&self) -> EventSender {
+        EventSender::new(self.input.clone())
+    }
+
+    pub fn send(&self, command: Command) {
+        let _ = self.input.send(Input::Command(command));
+    }
+
+    // Starts with the requests still open and the transfers so far, then every event as it happens
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::channel();
+        let _ = self.input.send(Input::Subscribe(sender));
+        receiver
+    }
+}
+
+// Finished transfers kept around by the engine and the GUI, the older ones are in the history (common::history)
+pub const MAX_FINISHED_TRANSFERS: usize = 500;
+
+// The view of one subscriber, rebuilt from the events
+#[derive(Default)]
+pub struct State {
+    pub requests: BTreeMap<u32, RequestData>,
+    pub transfers: HashMap<u32, TransferState>,
+}
+
+impl State {
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::RequestReceived { id, request } => {
+                self.requests.insert(*id, request.clone());
+            }
+            Event::RequestClosed { id, .. } => {
+                self.requests.remove(id);
+            }
+            Event::TransferAdded { id, state } => {
+                self.transfers.insert(*id, state.clone());
+            }
+            Event::TransferProgress { id, bytes_done } => {
+                if let Some(state) = self.transfers.get_mut(id) {
+                    state.set_progress(*bytes_done);
+                }
+            }
+            Event::TransferChanged { id, ttype } => {
+                if let Some(state) = self.transfers.get_mut(id) {
+                    state.set_type(ttype.clone());
+                }
+            }
+            Event::TransferLog { id, message } => {
+                if let Some(state) = self.transfers.get_mut(id) {
+                    state.log(message.clone());
+                }
+            }
+            Event::SnapshotEnd => (),
+        }
+    }
+
+    // Drops the transfers that ended first. Not done by apply, whoever waits for its own transfers would miss them
+    pub fn prune_finished(&mut self, keep: usize) {
+        let mut finished: Vec<(std::time::SystemTime, u32)> = self.transfers.iter()
+            .filter(|(_, state)| state.ttype.is_finished())
+            .map(|(id, state)| (state.ended_at.unwrap_or(state.started_at), *id))
+            .collect();
+        if finished.len() <= keep {
+            return;
+        }
+        finished.sort();
+        for (_, id) in finished.iter().take(finished.len() - keep) {
+            self.transfers.remove(id);
+        }
+    }
+
+    // Replayed to new subscribers
+    fn snapshot(&self) -> Vec<Event> {
+        let requests = self.requests.iter().map(|(id, request)| Event::RequestReceived { id: *id, request: request.clone() });
+        let transfers = self.transfers.iter().map(|(id, state)| Event::TransferAdded { id: *id, state: state.clone() });
+        requests.chain(transfers).chain([Event::SnapshotEnd]).collect()
+    }
+}
+
+fn run(input: Receiver<Input>, events: EventSender, mut on_event: impl FnMut(&Event)) {
+    let mut state = State::default();
+    let mut replies: HashMap<u32, Sender<Vec<String>>> = HashMap::new();
+    let mut subscribers: Vec<Sender<Event>> = Vec::new();
+
+    for message in input {
+        let event = match message {
+            Input::Event(event) => event,
+            Input::Request { id, request, reply } => {
+                replies.insert(id, reply);
+                Event::RequestReceived { id, request }
+            }
+            Input::Command(Command::AnswerRequest { id, accepted_files }) => {
+                // Answered twice, or the sender is gone
+                let Some(reply) = replies.remove(&id) else {
+                    continue;
+                };
+                let _ = reply.send(accepted_files.clone());
+                Event::RequestClosed { id, accepted_files }
+            }
+            Input::Command(Command::SendFiles { peer, files }) => {
+                let events = events.clone();
+                thread::spawn(move || client::control_connection(*peer, files, events));
+                continue;
+            }
+            Input::Subscribe(subscriber) => {
+                for event in state.snapshot() {
+                    let _ = subscriber.send(event);
+                }
+                subscribers.push(subscriber);
+                continue;
+            }
+        };
+
+        state.apply(&event);
+        on_event(&event);
+        if matches!(&event, Event::TransferAdded { .. } | Event::TransferChanged { .. }) {
+            state.prune_finished(MAX_FINISHED_TRANSFERS);
+        }
+
+        // Subscribers that went away are dropped
+        subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,11 @@
+/*
+Discovery, the transfer protocol and the engine that runs them, without any user interface.
+Start a Node, subscribe to its events and answer the requests, see node.rs
+*/
+pub mod common;
+pub mod networking;
+pub mod engine;
+pub mod node;
+pub mod daemon;
+
+pub use node::Node;
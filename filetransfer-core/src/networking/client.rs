@@ -0,0 +1,532 @@
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream};
+use std::io::{BufRead, Read, Seek, SeekFrom, Write};
+use std::fs::File;
+use std::sync::{Arc, Mutex};
+
+use crate::common::notify::Notify;
+use serde::{Deserialize, Serialize};
+
+use crate::common::{config, counter, delta, manifest, sparse, xattrs};
+use crate::common::manifest::{EntryKind, FileEntry};
+use crate::common::metadata::FileMetadata;
+use crate::common::hash::hash_file_sha256;
+use crate::common::transfer_state::{TransferError, TransferState, TransferType};
+use crate::engine::events::{EventSender, ProgressReporter};
+use crate::common::identity::Identity;
+use crate::networking::{discovery, interfaces, ports, sockets};
+use crate::networking::error::NetError;
+use crate::networking::ports::{DiscoverySockets, Ports};
+use crate::networking::favorites::Favorites;
+use crate::networking::presence::PresenceTable;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PingResponse {
+    pub addr: std::net::SocketAddr,
+    pub os: String,
+    pub hostname: String,
+    // Name of the local interface the peer was found through
+    pub interface: String,
+    // Empty for peers running an older version
+    pub device_id: String,
+    pub display_name: String,
+    pub version: String,
+    pub control_port: u16,
+    pub data_port: u16,
+    pub capabilities: Vec<String>,
+}
+
+impl Default for PingResponse {
+    fn default() -> Self {
+        PingResponse {
+            addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)),
+            os: String::new(),
+            hostname: String::new(),
+            interface: String::new(),
+            device_id: String::new(),
+            display_name: String::new(),
+            version: String::new(),
+            control_port: ports::DEFAULT_CONTROL,
+            data_port: ports::DEFAULT_DATA,
+            capabilities: Vec::new(),
+        }
+    }
+}
+
+// A peer answering on several addresses or interfaces is still a single entry
+#[derive(Hash, PartialEq, Eq)]
+enum PeerKey<'a> {
+    Device(&'a str),
+    Address(SocketAddr),
+}
+
+impl PingResponse {
+    pub fn new(addr: std::net::SocketAddr, os: String, hostname: String, interface: String) -> Self {
+        Self {
+            addr: sockets::normalize(addr),
+            os, 
+            hostname,
+            interface,
+            ..Default::default()
+        }
+    }
+
+    fn key(&self) -> PeerKey<'_> {
+        if self.device_id.is_empty() {
+            PeerKey::Address(self.addr)
+        } else {
+            PeerKey::Device(&self.device_id)
+        }
+    }
+
+    // This machine through 127.0.0.1, listed on purpose for testing
+    pub fn loopback(identity: &Identity, ports: &Ports) -> Self {
+        PingResponse {
+            addr: SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::LOCALHOST, ports.control)),
+            os: std::env::consts::OS.to_string(),
+            hostname: whoami::username(),
+            interface: String::from("loopback"),
+            device_id: identity.device_id.clone(),
+            display_name: format!("This device ({})", identity.display_name),
+            version: discovery::VERSION.to_string(),
+            capabilities: discovery::CAPABILITIES.iter().map(|cap| cap.to_string()).collect(),
+            control_port: ports.control,
+            data_port: ports.data,
+        }
+    }
+
+    // Stable across rounds, the address for peers that don't send a device ID
+    pub fn peer_id(&self) -> String {
+        match self.key() {
+            PeerKey::Device(id) => id.to_string(),
+            PeerKey::Address(addr) => addr.to_string(),
+        }
+    }
+
+    // The display name when the peer has one, the username otherwise
+    pub fn name(&self) -> &str {
+        if self.display_name.is_empty() { &self.hostname } else { &self.display_name }
+    }
+}
+
+impl PartialEq for PingResponse {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for PingResponse {}
+
+impl std::hash::Hash for PingResponse {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+const CONNECT_ATTEMPTS: u32 = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+// The receiver computes the delta signature of its copy before answering a data header
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(300);
+// Waiting for the answer to a request. The receiver gives up on its user first (server::ANSWER_TIMEOUT),
+// the rest is left for the local copies it makes before answering
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(600);
+// The slowest hashing we wait for once the data is sent, bigger files get longer to be verified
+const MIN_VERIFY_RATE: u64 = 10 * 1024 * 1024;
+
+// Retries with a growing delay, the data listener may take a moment after the request is accepted
+fn connect(dest: SocketAddr) -> Result<TcpStream, TransferError> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+
+    loop {
+        match TcpStream::connect_timeout(&dest, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                eprintln!("Connected to {}", dest);
+                return Ok(stream);
+            }
+            Err(e) if attempt < CONNECT_ATTEMPTS => {
+                eprintln!("Failed to connect to {} (attempt {}): {}", dest, attempt, e);
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn failed_state(original_path: &str, peer: SocketAddr, reason: TransferError) -> TransferState {
+    let size = std::fs::metadata(original_path).map(|m| m.len()).unwrap_or(0);
+    TransferState::new(TransferType::Error(reason), original_path.to_string(), String::new(), peer, size)
+}
+
+// Uses the ports the peer advertised, the defaults for older versions
+pub fn control_connection(peer: PingResponse, files: Vec<String>, events: EventSender) {
+    let mut dest = peer.addr;
+    dest.set_port(peer.control_port);
+
+    let batch = Some(counter::get_inc());
+
+    // Files that can't be read fail right away, a made up hash would match another file on the receiver
+    let mut sent_files = Vec::new();
+    let mut entries = Vec::new();
+    for file in files {
+        match file_entry(&file) {
+            Ok(entry) => {
+                sent_files.push(file);
+                entries.push(entry);
+            }
+            Err(reason) => {
+                eprintln!("Cannot read {}: {}", file, reason);
+                let mut state = failed_state(&file, dest, reason);
+                state.batch = batch;
+                events.add(counter::get_inc(), state);
+            }
+        }
+    }
+    if entries.is_empty() {
+        return;
+    }
+
+    // Nothing was sent, every file of the request failed the same way
+    if let Err(reason) = send_request(dest, peer.data_port, &sent_files, &entries, batch, &events) {
+        eprintln!("Request to {} failed: {}", dest, reason);
+
+        for file in sent_files.iter() {
+            let mut state = failed_state(file, dest, reason.clone());
+            state.batch = batch;
+            events.add(counter::get_inc(), state);
+        }
+    }
+}
+
+// The content hash lets the receiver skip files it already has
+fn file_entry(path: &str) -> Result<FileEntry, TransferError> {
+    let name = std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy().into_owned();
+
+    // Symlinks are sent as symlinks, not followed
+    if let Ok(target) = std::fs::read_link(path) {
+        return Ok(FileEntry {
+            name,
+            size: 0,
+            hash: [0u8; 32],
+            kind: EntryKind::Symlink(target.to_string_lossy().into_owned()),
+            xattrs: xattrs::read(path),
+            metadata: None,
+        });
+    }
+
+    let metadata = std::fs::metadata(path)?;
+    Ok(FileEntry {
+        name,
+        size: metadata.len(),
+        hash: hash_file_sha256(path)?,
+        kind: EntryKind::File,
+        xattrs: xattrs::read(path),
+        metadata: Some(FileMetadata::from_file(&metadata)),
+    })
+}
+
+fn send_request(dest: SocketAddr, data_port: u16, files: &[String], entries: &[FileEntry], batch: Option<u32>, events: &EventSender) -> Result<(), TransferError> {
+    let mut data_dest = dest;
+    data_dest.set_port(data_port);
+
+    let mut stream = connect(dest)?;
+
+    let req_message = manifest::encode_request(entries);
+
+    stream.write_all(req_message.as_bytes())?;
+
+    // An expired read is reported as TransferError::Timeout
+    stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    if response.starts_with("REJECT") {
+        eprintln!("Server rejected the request: {}", response);
+        return Err(TransferError::Rejected);
+    } else if !response.starts_with("ACCEPT") {
+        eprintln!("Unexpected response from server: {}", response);
+        return Err(TransferError::Io(String::from("unexpected response from the receiver")));
+    }
+
+    let (accepted_files, already_there) = manifest::parse_response(&response);
+
+    if accepted_files.is_empty() && already_there.is_empty() {
+        eprintln!("No files accepted by the server.");
+        return Err(TransferError::Rejected);
+    }
+
+    for (original_path, entry) in files.iter().zip(entries.iter()) {
+        if accepted_files.contains(&entry.name) {
+            let next_key = counter::get_inc();
+            let mut tmp = TransferState::new(TransferType::ComputingHash, original_path.clone(), String::new(), dest, entry.size);
+            tmp.batch = batch;
+            tmp.hash = Some(entry.hash);
+            events.add(next_key, tmp);
+
+            std::thread::spawn({
+                let file = original_path.clone();
+                let hash = entry.hash;
+                let events = events.clone();
+                move || {
+                    data_connection(next_key, data_dest, file, hash, events);
+                }
+            });
+        } else if already_there.contains(&entry.name) {
+            // The receiver copies these locally, nothing to send
+            let mut tmp = TransferState::new(TransferType::CompletelySent, original_path.clone(), entry.name.clone(), dest, entry.size);
+            tmp.log("The receiver already had this content");
+            tmp.batch = batch;
+            tmp.hash = Some(entry.hash);
+            events.add(counter::get_inc(), tmp);
+        } else {
+            // Left out by the user on the other side
+            let mut tmp = failed_state(original_path, dest, TransferError::Rejected);
+            tmp.batch = batch;
+            events.add(counter::get_inc(), tmp);
+        }
+    }
+
+    Ok(())
+}
+
+// Always ends with CompletelySent or Error
+pub fn data_connection(key: u32, dest: SocketAddr, file_str: String, hash: [u8; 32], events: EventSender) {
+    match send_file(key, dest, &file_str, hash, &events) {
+        Ok(()) => events.set_type(key, TransferType::CompletelySent),
+        Err(reason) => {
+            eprintln!("Sending {} failed: {}", file_str, reason);
+            events.set_type(key, TransferType::Error(reason));
+        }
+    }
+}
+
+fn send_file(key: u32, dest: SocketAddr, file_str: &str, hash: [u8; 32], events: &EventSender) -> Result<(), TransferError> {
+    let chunk_size = config::current().chunk_size;
+    let mut file = File::open(file_str)?;
+
+    let mut stream = connect(dest)?;
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT))?;
+    
+    let mut filename_bytes = [0u8; 256];
+    let filename_str = std::path::Path::new(file_str)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    let bytes = filename_str.as_bytes();
+    let len = bytes.len().min(256);
+    filename_bytes[..len].copy_from_slice(&bytes[..len]);
+    stream.write_all(&filename_bytes)?;
+
+    let file_metadata = file.metadata()?;
+    let file_size = file_metadata.len();
+    stream.write_all(&file_size.to_le_bytes())?;
+
+    stream.write_all(&hash)?;
+
+    stream.write_all(&FileMetadata::from_file(&file_metadata).to_bytes())?;
+
+    let mut response = String::new();
+    let mut reader = std::io::BufReader::new(&stream);
+    reader.read_line(&mut response)?;
+    
+    let mut progress = ProgressReporter::new(events.clone(), key);
+
+    if response.starts_with("DELTA") {
+        // The receiver has an older version of the file, only send what changed
+        eprintln!("File {} accepted for delta transfer", file_str);
+        events.log(key, "The receiver has an older version, sending only the changes");
+
+        let signature = delta::read_signature(&mut reader)?;
+
+        events.set_type(key, TransferType::Sending);
+        let mut writer = std::io::BufWriter::new(&stream);
+        let mut file_reader = std::io::BufReader::new(file);
+        delta::write_delta(&mut file_reader, &signature, &mut writer, |bytes| progress.report(bytes))?;
+        writer.flush()?;
+    } else if response.starts_with("ACCEPT") {
+        eprintln!("File {} accepted for sending", file_str);
+
+        // `reader` still borrows the stream, writes go through a shared reference
+        let mut writer = &stream;
+
+        // Holes are not sent, only the data extents
+        let extents = sparse::data_extents(&file, file_size);
+        sparse::write_extent_map(&mut writer, &extents)?;
+
+        let mut buffer = vec![0u8; chunk_size];
+
+        eprintln!("Starting file transfer for {}", file_str);
+        events.set_type(key, TransferType::Sending);
+
+        for (offset, len) in extents {
+            file.seek(SeekFrom::Start(offset))?;
+
+            let mut sent: u64 = 0;
+            while sent < len {
+                let to_read = (len - sent).min(chunk_size as u64) as usize;
+                let n = file.read(&mut buffer[..to_read])?;
+                if n == 0 {
+                    // The file shrank while sending, the receiver would wait for the rest
+                    return Err(TransferError::Io(String::from("file changed while sending")));
+                }
+
+                sent += n as u64;
+
+                writer.write_all(&buffer[..n])?;
+
+                progress.report(offset + sent);
+            }
+        }
+    } else {
+        eprintln!("File {} rejected: {}", file_str, response);
+        return Err(TransferError::Rejected);
+    }
+
+    progress.flush();
+
+    // The receiver hashes the whole file before answering
+    events.log(key, "Waiting for the receiver to verify the hash");
+    stream.set_read_timeout(Some(RESPONSE_TIMEOUT + Duration::from_secs(file_size / MIN_VERIFY_RATE)))?;
+    let mut verdict = String::new();
+    reader.read_line(&mut verdict)?;
+
+    if verdict.starts_with("VERIFIED") {
+        Ok(())
+    } else if verdict.starts_with("MISMATCH") {
+        Err(TransferError::HashMismatch)
+    } else if verdict.starts_with("FAILED") {
+        Err(TransferError::Io(String::from("the receiver could not save the file")))
+    } else {
+        // Crashed or dropped the connection, nothing says the file made it
+        Err(TransferError::Io(String::from("the receiver closed the connection before verifying the file")))
+    }
+}
+
+// Link-local all nodes group, every IPv6 host on the link receives it
+const DISCOVERY_GROUP_V6: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+pub fn parse_ping_reply(data: &[u8], src: SocketAddr, interface: String) -> PingResponse {
+    let text = String::from_utf8_lossy(data);
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let hostname = lines.next().unwrap_or("").to_string();
+    let os = lines.next().unwrap_or("").to_string();
+    let mut response = PingResponse::new(src, os, hostname, interface);
+    for line in lines {
+        if let Some((key, value)) = line.split_once('=') {
+            discovery::apply_property(&mut response, key, value);
+        }
+    }
+    response
+}
+
+pub(crate) fn is_self(peer: &PingResponse, own_device_id: &str, local_addresses: &HashSet<std::net::IpAddr>) -> bool {
+    if peer.device_id.is_empty() {
+        local_addresses.contains(&peer.addr.ip())
+    } else {
+        peer.device_id == own_device_id
+    }
+}
+
+// `mdns_found` holds the peers found through mDNS and `favorites` the ones added by hand,
+// they count as seen while they are announced or answer their probe.
+// `presence` decides which peers are listed, `responders_list` is refreshed from it after every round
+pub fn info_socket(sockets: DiscoverySockets, responders_list : &mut Arc<Mutex<HashSet<PingResponse>>>, identity: &Identity, presence: &Arc<Mutex<PresenceTable>>, favorites: &Arc<Mutex<Favorites>>, mdns_found: &Arc<Mutex<HashSet<PingResponse>>>, notify: &Notify) -> Result<(), NetError> {
+    let DiscoverySockets { v4: socket, v6: socket_v6, info_port } = sockets;
+
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+    // IPv6 has no broadcast, the ping goes to the link-local multicast group of every interface
+    if let Some(socket_v6) = &socket_v6 {
+        socket_v6.set_read_timeout(Some(Duration::from_millis(50)))?;
+    }
+    
+
+    let broadcast_addr = SocketAddrV4::new(Ipv4Addr::BROADCAST, info_port);
+    //let broadcast_addr = "192.168.1.9:24934";
+    let mut responders: Vec<(PingResponse, Option<Duration>)> = Vec::new();
+
+    // Loop to send ping, receive responses, and repeat after a delay
+    loop {
+        responders.clear();
+
+        // The limited broadcast only leaves through one interface, so ping every subnet directly too.
+        // Interfaces are listed again every round, they come and go (Wi-Fi, VPNs, Docker bridges)
+        let local_interfaces = interfaces::ipv4_interfaces();
+        let local_interfaces_v6 = interfaces::ipv6_interfaces();
+
+        // Signed again every round, the receivers reject old timestamps
+        let ping = discovery::ping_message(identity);
+        let ping_message = ping.as_bytes();
+
+        let sent = Instant::now();
+        // Fails while the machine has no network, the next rounds try again
+        if let Err(e) = socket.send_to(ping_message, broadcast_addr) {
+            eprintln!("Failed to send ping: {}", e);
+        }
+
+        for iface in local_interfaces.iter() {
+            if let Err(e) = socket.send_to(ping_message, SocketAddrV4::new(iface.broadcast, info_port)) {
+                eprintln!("Failed to send ping on {}: {}", iface.name, e);
+            }
+        }
+
+        if let Some(socket_v6) = &socket_v6 {
+            for iface in local_interfaces_v6.iter() {
+                // The scope id selects the interface the multicast leaves from
+                if let Err(e) = socket_v6.send_to(ping_message, SocketAddrV6::new(DISCOVERY_GROUP_V6, info_port, 0, iface.index)) {
+                    eprintln!("Failed to send IPv6 ping on {}: {}", iface.name, e);
+                }
+            }
+        }
+
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            let mut buf = [0u8; 1024];
+            if let Ok((amt, src)) = socket.recv_from(&mut buf) {
+                let interface = interfaces::interface_for(&local_interfaces, src.ip());
+                responders.push((parse_ping_reply(&buf[..amt], src, interface), Some(sent.elapsed())));
+            }
+
+            if let Some(socket_v6) = &socket_v6
+                && let Ok((amt, src)) = socket_v6.recv_from(&mut buf)
+            {
+                let interface = match src {
+                    SocketAddr::V6(v6) => local_interfaces_v6.iter()
+                        .find(|iface| iface.index == v6.scope_id())
+                        .map(|iface| iface.name.clone())
+                        .unwrap_or_default(),
+                    SocketAddr::V4(_) => String::new(),
+                };
+                responders.push((parse_ping_reply(&buf[..amt], src, interface), Some(sent.elapsed())));
+            }
+        }
+
+        // We answer our own broadcast, once per interface. Older peers have no ID, use the addresses for them
+        let local_addresses = interfaces::local_addresses();
+        responders.retain(|(r, _)| !is_self(r, &identity.device_id, &local_addresses));
+
+        let mut other_peers: Vec<PingResponse> = mdns_found.lock().unwrap().iter().cloned().collect();
+        other_peers.extend(favorites.lock().unwrap().online.values().cloned());
+        for peer in other_peers.into_iter().filter(|peer| !is_self(peer, &identity.device_id, &local_addresses)) {
+            if !responders.iter().any(|(r, _)| *r == peer || r.addr.ip() == peer.addr.ip()) {
+                responders.push((peer, None));
+            }
+        }
+
+        // Peers are only dropped after missing a few rounds, a lost reply doesn't make them flicker
+        let mut presence = presence.lock().unwrap();
+        presence.update_round(std::mem::take(&mut responders));
+        *responders_list.lock().unwrap() = presence.peers();
+        drop(presence);
+        
+        notify();
+
+        // Wait a few seconds before next broadcast
+        std::thread::sleep(config::current().discovery_interval());
+    }
+    
+}
@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::common::identity::{self, Identity};
+use crate::common::paths;
+
+/*
+Paired devices, whose signed pings are answered in contacts only mode.
+Pairing is one way: entering the pairing code of device A on device B lets B answer A.
+The code is the device ID and the pairing key of A joined by a dash.
+
+Stored in the config directory, one contact per line:
+
+5f1c0a9e2b7d4c3a8e6f1b2d3c4a5e6f 0c7d2e9f4a1b6c3d8e5f2a7b4c9d1e6f Office laptop
+*/
+
+#[derive(Clone, Debug)]
+pub struct Contact {
+    pub device_id: String,
+    pub pairing_key: String,
+    pub name: String,
+}
+
+#[derive(Default)]
+pub struct Contacts {
+    pub list: Vec<Contact>,
+}
+
+fn contacts_file() -> PathBuf {
+    paths::config_dir().join("contacts")
+}
+
+fn is_hex_id(value: &str) -> bool {
+    value.len() == 32 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn pairing_code(identity: &Identity) -> String {
+    format!("{}-{}", identity.device_id, identity.pairing_key)
+}
+
+// Returns the device ID and the pairing key
+pub fn parse_pairing_code(code: &str) -> Option<(String, String)> {
+    let (device_id, pairing_key) = code.trim().split_once('-')?;
+    if !is_hex_id(device_id) || !is_hex_id(pairing_key) {
+        return None;
+    }
+    Some((device_id.to_ascii_lowercase(), pairing_key.to_ascii_lowercase()))
+}
+
+impl Contacts {
+    pub fn load() -> Self {
+        let list = fs::read_to_string(contacts_file())
+            .map(|content| content.lines()
+                .filter_map(|line| {
+                    let mut parts = line.splitn(3, ' ');
+                    let device_id = parts.next()?.to_string();
+                    let pairing_key = parts.next()?.to_string();
+                    let name = parts.next().unwrap_or("").to_string();
+                    Some(Contact { device_id, pairing_key, name })
+                })
+                .collect())
+            .unwrap_or_default();
+        Contacts { list }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(paths::config_dir())?;
+        let content: String = self.list.iter()
+            .map(|contact| format!("{} {} {}\n", contact.device_id, contact.pairing_key, contact.name))
+            .collect();
+        fs::write(contacts_file(), content)?;
+
+        // The keys let anyone sign pings as our contacts
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(contacts_file(), fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    // Pairing again with the same device replaces the old key
+    pub fn add(&mut self, code: &str, name: &str) -> Result<(), &'static str> {
+        let (device_id, pairing_key) = parse_pairing_code(code).ok_or("Invalid pairing code")?;
+        let name = identity::sanitize_display_name(name);
+        self.list.retain(|contact| contact.device_id != device_id);
+        self.list.push(Contact { device_id, pairing_key, name });
+        Ok(())
+    }
+
+    pub fn remove(&mut self, device_id: &str) {
+        self.list.retain(|contact| contact.device_id != device_id);
+    }
+
+    pub fn key_for(&self, device_id: &str) -> Option<&str> {
+        self.list.iter()
+            .find(|contact| contact.device_id == device_id)
+            .map(|contact| contact.pairing_key.as_str())
+    }
+}
@@ -0,0 +1,136 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::common::hash::{bytes_to_hex, hex_to_bytes};
+use crate::common::identity::Identity;
+use crate::networking::client::PingResponse;
+use crate::networking::contacts::Contacts;
+use crate::networking::ports::Ports;
+
+/*
+ping, signed with the pairing key of the sender (HMAC-SHA256 of everything before the sig line).
+Older versions only send the first line, they are only answered by visible receivers:
+
+ping\n
+from=5f1c0a9e2b7d4c3a8e6f1b2d3c4a5e6f\n
+ts=1760875200\n
+sig=3b7e...\n
+
+ping reply, the first two lines are what older versions send and understand:
+
+username\n
+os\n
+id=5f1c0a9e2b7d4c3a8e6f1b2d3c4a5e6f\n
+name=Office laptop\n
+version=0.1.0\n
+ports=24934,24935\n
+caps=delta,dedup,metadata,xattr,symlink,sparse,ipv6\n
+
+The same key=value pairs are published in the mDNS TXT record. Unknown keys are ignored.
+*/
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+// Signed pings older than this are not answered, a captured one can't be replayed for long
+const MAX_PING_AGE_SECS: u64 = 60;
+
+pub const CAPABILITIES: &[&str] = &["delta", "dedup", "metadata", "xattr", "symlink", "sparse", "ipv6"];
+
+// `ports` are the bound ones, see networking::ports
+pub fn properties(identity: &Identity, ports: &Ports) -> Vec<(&'static str, String)> {
+    vec![
+        ("hostname", whoami::username()),
+        ("os", std::env::consts::OS.to_string()),
+        ("id", identity.device_id.clone()),
+        ("name", identity.display_name.clone()),
+        ("version", VERSION.to_string()),
+        ("ports", format!("{},{}", ports.control, ports.data)),
+        ("caps", CAPABILITIES.join(",")),
+    ]
+}
+
+pub fn ping_reply(identity: &Identity, ports: &Ports) -> String {
+    let mut reply = format!("{}\n{}\n", whoami::username(), std::env::consts::OS);
+    for (key, value) in properties(identity, ports).into_iter().skip(2) {
+        reply.push_str(&format!("{}={}\n", key, value));
+    }
+    reply
+}
+
+pub fn apply_property(response: &mut PingResponse, key: &str, value: &str) {
+    match key {
+        "hostname" => response.hostname = value.to_string(),
+        "os" => response.os = value.to_string(),
+        "id" => response.device_id = value.to_string(),
+        "name" => response.display_name = value.to_string(),
+        "version" => response.version = value.to_string(),
+        "ports" => {
+            let mut ports = value.split(',').map(|port| port.trim().parse::<u16>());
+            if let (Some(Ok(control)), Some(Ok(data))) = (ports.next(), ports.next()) {
+                response.control_port = control;
+                response.data_port = data;
+            }
+        }
+        "caps" => {
+            response.capabilities = value.split(',')
+                .map(|cap| cap.trim().to_string())
+                .filter(|cap| !cap.is_empty())
+                .collect();
+        }
+        _ => {}
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn ping_mac(pairing_key: &str, payload: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pairing_key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+pub fn ping_message(identity: &Identity) -> String {
+    let payload = format!("ping\nfrom={}\nts={}\n", identity.device_id, unix_time());
+    let signature = ping_mac(&identity.pairing_key, &payload).finalize().into_bytes();
+    format!("{}sig={}\n", payload, bytes_to_hex(&signature))
+}
+
+// True when the ping comes from one of our contacts and is recent
+pub fn verify_ping(data: &[u8], contacts: &Contacts) -> bool {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return false;
+    };
+    let Some(sig_start) = text.find("sig=") else {
+        return false;
+    };
+    let (payload, sig_line) = text.split_at(sig_start);
+
+    let mut from = None;
+    let mut ts = None;
+    for line in payload.lines() {
+        match line.split_once('=') {
+            Some(("from", value)) => from = Some(value),
+            Some(("ts", value)) => ts = value.parse::<u64>().ok(),
+            _ => {}
+        }
+    }
+
+    let (Some(from), Some(ts)) = (from, ts) else {
+        return false;
+    };
+    if unix_time().abs_diff(ts) > MAX_PING_AGE_SECS {
+        return false;
+    }
+    let Some(pairing_key) = contacts.key_for(from) else {
+        return false;
+    };
+
+    let Some(signature) = hex_to_bytes(sig_line.trim_start_matches("sig=").trim()) else {
+        return false;
+    };
+
+    ping_mac(pairing_key, payload).verify_slice(&signature).is_ok()
+}
@@ -0,0 +1,60 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum NetError {
+    // A listening socket couldn't be bound
+    Bind { port: u16, source: io::Error },
+    Io(io::Error),
+    // The peer sent something we don't understand
+    Protocol(String),
+}
+
+impl NetError {
+    pub fn bind(port: u16) -> impl FnOnce(io::Error) -> NetError {
+        move |source| NetError::Bind { port, source }
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetError::Bind { port, source } => match source.kind() {
+                io::ErrorKind::AddrInUse => write!(f, "port {} in use", port),
+                io::ErrorKind::PermissionDenied => write!(f, "no permission to use port {}", port),
+                _ => write!(f, "cannot use port {}: {}", port, source),
+            },
+            NetError::Io(e) => write!(f, "{}", e),
+            NetError::Protocol(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for NetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NetError::Bind { source, .. } => Some(source),
+            NetError::Io(e) => Some(e),
+            NetError::Protocol(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        NetError::Io(e)
+    }
+}
+
+// Errors that only concern one connection or datagram, the listener keeps working
+pub fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+    )
+}
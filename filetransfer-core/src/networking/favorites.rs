@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::common::notify::Notify;
+
+use crate::common::paths;
+use crate::networking::client::{self, PingResponse};
+use crate::common::identity::Identity;
+use crate::networking::{discovery, interfaces, ports, sockets};
+
+/*
+Peers added by hand, for networks where the broadcast ping doesn't reach them (VPNs, guest Wi-Fi).
+Stored in the config directory, one address or hostname per line. The port is the info port
+of the peer (networking::ports), the default one when missing:
+
+192.168.1.20
+nas.local
+[fd00::12]:24934
+*/
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default, Clone)]
+pub struct Favorites {
+    pub addresses: Vec<String>,
+    // Last successful probe of every address, missing when it didn't answer
+    pub online: HashMap<String, PingResponse>,
+}
+
+fn favorites_file() -> PathBuf {
+    paths::config_dir().join("favorites")
+}
+
+impl Favorites {
+    pub fn load() -> Self {
+        let addresses = fs::read_to_string(favorites_file())
+            .map(|content| content.lines().map(|line| line.trim().to_string()).filter(|line| !line.is_empty()).collect())
+            .unwrap_or_default();
+        Favorites { addresses, online: HashMap::new() }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(paths::config_dir())?;
+        let mut content = self.addresses.join("\n");
+        content.push('\n');
+        fs::write(favorites_file(), content)
+    }
+
+    // Returns false when the address is empty or already there
+    pub fn add(&mut self, address: &str) -> bool {
+        let address = address.trim();
+        if address.is_empty() || self.addresses.iter().any(|known| known == address) {
+            return false;
+        }
+        self.addresses.push(address.to_string());
+        true
+    }
+
+    pub fn remove(&mut self, address: &str) {
+        self.addresses.retain(|known| known != address);
+        self.online.remove(address);
+    }
+
+    // Resolves the hostnames, call it without holding a lock
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        self.online.values().any(|peer| peer.addr.ip() == ip)
+            || self.addresses.iter().any(|address| resolve(address, ports::DEFAULT_INFO).iter().any(|addr| sockets::normalize_ip(addr.ip()) == ip))
+    }
+}
+
+// Plain IPs and hostnames get `default_port`
+fn resolve(address: &str, default_port: u16) -> Vec<SocketAddr> {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return vec![addr];
+    }
+    if let Ok(ip) = address.parse::<IpAddr>() {
+        return vec![SocketAddr::new(ip, default_port)];
+    }
+    address.to_socket_addrs()
+        .or_else(|_| (address, default_port).to_socket_addrs())
+        .map(|addrs| addrs.collect())
+        .unwrap_or_default()
+}
+
+fn probe_udp(addr: SocketAddr, identity: &Identity) -> io::Result<PingResponse> {
+    let local: SocketAddr = match addr {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_read_timeout(Some(PROBE_TIMEOUT))?;
+    socket.send_to(discovery::ping_message(identity).as_bytes(), addr)?;
+
+    let mut buf = [0u8; 1024];
+    let (n, src) = socket.recv_from(&mut buf)?;
+    let interface = interfaces::interface_for(&interfaces::ipv4_interfaces(), src.ip());
+    Ok(client::parse_ping_reply(&buf[..n], src, interface))
+}
+
+// Only tells that something listens on the port (info and control share it by default), there are no details about the peer
+fn probe_tcp(addr: SocketAddr, address: &str) -> io::Result<PingResponse> {
+    let stream = TcpStream::connect_timeout(&addr, PROBE_TIMEOUT)?;
+    let _ = stream.shutdown(Shutdown::Both);
+    let interface = interfaces::interface_for(&interfaces::ipv4_interfaces(), addr.ip());
+    Ok(PingResponse::new(addr, String::new(), address.to_string(), interface))
+}
+
+// Unicast ping first, a TCP connection to the control port when UDP is filtered.
+// None when nothing answered or when this node did: its own info listener answers an address of this machine
+pub fn probe(address: &str, identity: &Identity, info_port: u16) -> Option<PingResponse> {
+    let addrs = resolve(address, info_port);
+    addrs.iter()
+        .find_map(|addr| probe_udp(*addr, identity).ok())
+        .or_else(|| addrs.iter().find_map(|addr| probe_tcp(*addr, address).ok()))
+        .filter(|response| !client::is_self(response, &identity.device_id, &interfaces::local_addresses()))
+}
+
+pub fn probe_loop(favorites: Arc<Mutex<Favorites>>, identity: Identity, info_port: u16, notify: Notify) {
+    loop {
+        // Probed without holding the lock, a probe can take a few seconds
+        let addresses = favorites.lock().unwrap().addresses.clone();
+        let results: Vec<(String, Option<PingResponse>)> = addresses.into_iter()
+            .map(|address| {
+                let response = probe(&address, &identity, info_port);
+                (address, response)
+            })
+            .collect();
+
+        let mut favorites_lock = favorites.lock().unwrap();
+        for (address, response) in results {
+            match response {
+                Some(response) if favorites_lock.addresses.contains(&address) => {
+                    favorites_lock.online.insert(address, response);
+                }
+                _ => {
+                    favorites_lock.online.remove(&address);
+                }
+            }
+        }
+        drop(favorites_lock);
+
+        notify();
+
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+#[derive(Clone, Debug)]
+pub struct LocalInterface {
+    pub name: String,
+    pub ip: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub broadcast: Ipv4Addr,
+}
+
+impl LocalInterface {
+    pub fn contains(&self, ip: Ipv4Addr) -> bool {
+        u32::from(ip) & u32::from(self.netmask) == u32::from(self.ip) & u32::from(self.netmask)
+    }
+}
+
+// All the non loopback IPv4 interfaces (Ethernet, Wi-Fi, bridges, ...)
+pub fn ipv4_interfaces() -> Vec<LocalInterface> {
+    let Ok(interfaces) = if_addrs::get_if_addrs() else {
+        return Vec::new();
+    };
+
+    interfaces.into_iter()
+        .filter(|iface| !iface.is_loopback())
+        .filter_map(|iface| match iface.addr {
+            if_addrs::IfAddr::V4(addr) => Some(LocalInterface {
+                name: iface.name,
+                ip: addr.ip,
+                netmask: addr.netmask,
+                // Some platforms don't report it, compute the directed broadcast from the netmask
+                broadcast: addr.broadcast.unwrap_or_else(|| Ipv4Addr::from(u32::from(addr.ip) | !u32::from(addr.netmask))),
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// The interface whose subnet contains `ip`, that's where the peer was found
+pub fn interface_for(interfaces: &[LocalInterface], ip: IpAddr) -> String {
+    let IpAddr::V4(ip) = ip else {
+        return String::new();
+    };
+
+    interfaces.iter()
+        .find(|iface| iface.contains(ip))
+        .map(|iface| iface.name.clone())
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug)]
+pub struct LocalInterfaceV6 {
+    pub name: String,
+    pub index: u32,
+}
+
+// Non loopback interfaces with IPv6 enabled, one entry per interface
+pub fn ipv6_interfaces() -> Vec<LocalInterfaceV6> {
+    let Ok(interfaces) = if_addrs::get_if_addrs() else {
+        return Vec::new();
+    };
+
+    let mut result: Vec<LocalInterfaceV6> = Vec::new();
+    for iface in interfaces {
+        if iface.is_loopback() || !matches!(iface.addr, if_addrs::IfAddr::V6(_)) {
+            continue;
+        }
+        let Some(index) = iface.index else {
+            continue;
+        };
+        if !result.iter().any(|known| known.index == index) {
+            result.push(LocalInterfaceV6 { name: iface.name, index });
+        }
+    }
+    result
+}
+
+// Every address of this machine, loopback included, to recognize our own ping replies
+pub fn local_addresses() -> HashSet<IpAddr> {
+    let mut addresses: HashSet<IpAddr> = if_addrs::get_if_addrs()
+        .map(|interfaces| interfaces.into_iter().map(|iface| iface.ip()).collect())
+        .unwrap_or_default();
+    addresses.insert(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    addresses.insert(IpAddr::V6(std::net::Ipv6Addr::LOCALHOST));
+    addresses
+}
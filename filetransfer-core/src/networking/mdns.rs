@@ -0,0 +1,133 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::common::notify::Notify;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+
+use crate::common::identity::Identity;
+use crate::networking::client::PingResponse;
+use crate::networking::{discovery, interfaces};
+use crate::networking::ports::Ports;
+use crate::networking::presence::PresenceTable;
+
+// DNS-SD discovery, works where the UDP broadcast ping is dropped (VLANs, some routers, IPv6)
+pub const SERVICE_TYPE: &str = "_filetransfer._tcp.local.";
+
+pub struct Mdns {
+    daemon: ServiceDaemon,
+    instance: String,
+    fullname: String,
+    ports: Ports,
+}
+
+// Nodes started so far by this process, part of the instance name
+static STARTED: AtomicU32 = AtomicU32::new(0);
+
+// Unique per node, so several can run on the same host and even in the same process without replacing each other's record
+pub fn instance_name(identity: &Identity) -> String {
+    let id = identity.device_id.get(..8).unwrap_or(&identity.device_id);
+    format!("{}-{}-{}", id, std::process::id(), STARTED.fetch_add(1, Ordering::Relaxed))
+}
+
+fn preferred_address(addresses: &HashSet<IpAddr>) -> Option<IpAddr> {
+    addresses.iter().find(|ip| ip.is_ipv4() && !ip.is_loopback())
+        .or_else(|| addresses.iter().find(|ip| !ip.is_loopback()))
+        .or_else(|| addresses.iter().next())
+        .copied()
+}
+
+fn to_ping_response(info: &ServiceInfo) -> Option<PingResponse> {
+    let ip = preferred_address(info.get_addresses())?;
+    let interface = interfaces::interface_for(&interfaces::ipv4_interfaces(), ip);
+    let mut response = PingResponse::new(SocketAddr::new(ip, info.get_port()), String::new(), String::new(), interface);
+    for property in info.get_properties().iter() {
+        discovery::apply_property(&mut response, property.key(), property.val_str());
+    }
+    Some(response)
+}
+
+fn service_info(instance: &str, identity: &Identity, ports: &Ports) -> Result<ServiceInfo, mdns_sd::Error> {
+    let host_name = format!("{}.local.", instance);
+    let properties = discovery::properties(identity, ports);
+    Ok(ServiceInfo::new(SERVICE_TYPE, instance, &host_name, "", ports.control, &properties[..])?
+        .enable_addr_auto())
+}
+
+impl Mdns {
+    // Without `announce` it only browses until `update` is called, nothing about this device is sent
+    pub fn start(instance: &str, identity: &Identity, ports: Ports, announce: bool) -> Result<Self, mdns_sd::Error> {
+        let daemon = ServiceDaemon::new()?;
+
+        let info = service_info(instance, identity, &ports)?;
+        let fullname = info.get_fullname().to_string();
+
+        if announce {
+            daemon.register(info)?;
+        }
+
+        Ok(Mdns { daemon, instance: instance.to_string(), fullname, ports })
+    }
+
+    // Registering the same instance again announces the new TXT record
+    pub fn update(&self, identity: &Identity) -> Result<(), mdns_sd::Error> {
+        self.daemon.register(service_info(&self.instance, identity, &self.ports)?)
+    }
+
+    // Stops announcing this device, browsing goes on
+    pub fn withdraw(&self) -> Result<(), mdns_sd::Error> {
+        self.daemon.unregister(&self.fullname).map(|_| ())
+    }
+
+    // Resolved services are added to the same responders set filled by the UDP ping
+    pub fn browse(&self, responders: Arc<Mutex<HashSet<PingResponse>>>, presence: Arc<Mutex<PresenceTable>>, found: Arc<Mutex<HashSet<PingResponse>>>, notify: Notify) -> Result<(), mdns_sd::Error> {
+        let receiver = self.daemon.browse(SERVICE_TYPE)?;
+        let own_fullname = self.fullname.clone();
+
+        std::thread::spawn(move || {
+            let mut by_name: HashMap<String, PingResponse> = HashMap::new();
+
+            while let Ok(event) = receiver.recv() {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        if info.get_fullname() == own_fullname {
+                            continue;
+                        }
+                        let Some(response) = to_ping_response(&info) else {
+                            continue;
+                        };
+
+                        // A service can be resolved again, e.g. when it gets more addresses
+                        if let Some(previous) = by_name.insert(info.get_fullname().to_string(), response.clone()) {
+                            found.lock().unwrap().remove(&previous);
+                            responders.lock().unwrap().remove(&previous);
+                        }
+                        found.lock().unwrap().insert(response.clone());
+                        responders.lock().unwrap().insert(response);
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        if let Some(response) = by_name.remove(&fullname) {
+                            found.lock().unwrap().remove(&response);
+                            presence.lock().unwrap().remove(&response);
+                            responders.lock().unwrap().remove(&response);
+                        }
+                    }
+                    _ => continue,
+                }
+
+                notify();
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Drop for Mdns {
+    // Tells the other peers we are leaving
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
@@ -0,0 +1,12 @@
+pub mod client;
+pub mod server;
+pub mod mdns;
+pub mod interfaces;
+pub mod sockets;
+pub mod discovery;
+pub mod presence;
+pub mod favorites;
+pub mod contacts;
+pub mod ports;
+pub mod error;
+pub mod supervisor;
\ No newline at end of file
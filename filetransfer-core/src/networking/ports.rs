@@ -0,0 +1,123 @@
+use std::io;
+use std::net::{TcpListener, UdpSocket};
+
+use serde::{Deserialize, Serialize};
+
+use crate::networking::error::NetError;
+use crate::networking::sockets;
+
+/*
+control    TCP, file requests (see common::manifest)
+data       TCP, file contents
+info       UDP, answers the discovery ping. Shared by every instance on the host, peers ping it blindly.
+           Broadcast pings reach every instance, unicast ones (favorites) only one of them
+discovery  UDP, sends the discovery ping and gets the replies
+
+0 lets the OS pick a free port. Control and data ports are advertised in the ping reply and over mDNS,
+so peers always connect to the right ones.
+*/
+
+pub const DEFAULT_CONTROL: u16 = 24934;
+pub const DEFAULT_DATA: u16 = 24935;
+pub const DEFAULT_INFO: u16 = 24934;
+pub const DEFAULT_DISCOVERY: u16 = 24936;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ports {
+    pub control: u16,
+    pub data: u16,
+    pub info: u16,
+    pub discovery: u16,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Ports {
+            control: DEFAULT_CONTROL,
+            data: DEFAULT_DATA,
+            info: DEFAULT_INFO,
+            discovery: DEFAULT_DISCOVERY,
+        }
+    }
+}
+
+impl Ports {
+    // Lets several instances run on one host, e.g. for testing
+    pub fn ephemeral() -> Self {
+        Ports {
+            control: 0,
+            data: 0,
+            info: DEFAULT_INFO,
+            discovery: 0,
+        }
+    }
+}
+
+// The sockets answering the discovery ping
+pub struct InfoSockets {
+    pub v4: UdpSocket,
+    // IPv6 is optional, the v4 socket is enough to work
+    pub v6: Option<UdpSocket>,
+}
+
+// The sockets the discovery ping is sent from and the port the peers answer on
+pub struct DiscoverySockets {
+    pub v4: UdpSocket,
+    pub v6: Option<UdpSocket>,
+    pub info_port: u16,
+}
+
+pub fn bind_tcp(port: u16) -> Result<TcpListener, NetError> {
+    sockets::dual_stack_listener(port).map_err(NetError::bind(port))
+}
+
+pub fn bind_info(port: u16) -> Result<InfoSockets, NetError> {
+    let v4 = sockets::udp_v4(port, true).map_err(NetError::bind(port))?;
+    let v6 = sockets::udp_v6_only(port, true)
+        .inspect_err(|e| eprintln!("IPv6 discovery disabled: {}", e))
+        .ok();
+    Ok(InfoSockets { v4, v6 })
+}
+
+pub fn bind_discovery(port: u16, info_port: u16) -> Result<DiscoverySockets, NetError> {
+    let v4 = sockets::udp_v4(port, false).map_err(NetError::bind(port))?;
+    let v6_port = v4.local_addr()?.port();
+    let v6 = sockets::udp_v6_only(v6_port, false)
+        .or_else(|_| sockets::udp_v6_only(0, false))
+        .ok();
+    Ok(DiscoverySockets { v4, v6, info_port })
+}
+
+fn bound_port<T>(socket: &Result<T, NetError>, local_port: impl Fn(&T) -> io::Result<u16>, requested: u16) -> u16 {
+    socket.as_ref().ok().and_then(|socket| local_port(socket).ok()).unwrap_or(requested)
+}
+
+// Everything is bound before the threads start, so the real ports are known and advertised.
+// A socket that fails is bound again by its service supervisor (networking::supervisor)
+pub struct Listeners {
+    pub control: Result<TcpListener, NetError>,
+    pub data: Result<TcpListener, NetError>,
+    pub info: Result<InfoSockets, NetError>,
+    pub discovery: Result<DiscoverySockets, NetError>,
+    // The bound ports, the requested ones for the sockets that failed
+    pub ports: Ports,
+}
+
+impl Listeners {
+    pub fn bind(ports: Ports) -> Self {
+        let control = bind_tcp(ports.control);
+        let data = bind_tcp(ports.data);
+        let info = bind_info(ports.info);
+        let discovery = bind_discovery(ports.discovery, ports.info);
+
+        let bound = Ports {
+            control: bound_port(&control, |listener| Ok(listener.local_addr()?.port()), ports.control),
+            data: bound_port(&data, |listener| Ok(listener.local_addr()?.port()), ports.data),
+            info: ports.info,
+            discovery: bound_port(&discovery, |sockets| Ok(sockets.v4.local_addr()?.port()), ports.discovery),
+        };
+
+        Listeners { control, data, info, discovery, ports: bound }
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use crate::networking::client::PingResponse;
+
+// A single lost reply doesn't hide a peer, it has to miss this many discovery rounds in a row
+pub const MAX_MISSED_ROUNDS: u32 = 3;
+
+#[derive(Clone, Debug)]
+pub struct PeerStatus {
+    pub last_seen: Instant,
+    // None for peers only known through mDNS
+    pub rtt: Option<Duration>,
+    pub missed_rounds: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum PresenceEvent {
+    Joined(PingResponse),
+    Left(PingResponse),
+}
+
+pub struct PresenceTable {
+    peers: HashMap<String, (PingResponse, PeerStatus)>,
+    events: Sender<PresenceEvent>,
+}
+
+impl PresenceTable {
+    pub fn new(events: Sender<PresenceEvent>) -> Self {
+        PresenceTable {
+            peers: HashMap::new(),
+            events,
+        }
+    }
+
+    // Called once per discovery round with every peer that answered
+    pub fn update_round(&mut self, seen: Vec<(PingResponse, Option<Duration>)>) {
+        let now = Instant::now();
+        let mut seen_ids = HashSet::new();
+
+        for (peer, rtt) in seen {
+            let id = peer.peer_id();
+            if !seen_ids.insert(id.clone()) {
+                continue; // Already answered on another address this round
+            }
+
+            match self.peers.get_mut(&id) {
+                Some((known, status)) => {
+                    *known = peer;
+                    status.last_seen = now;
+                    status.rtt = rtt.or(status.rtt);
+                    status.missed_rounds = 0;
+                }
+                None => {
+                    let _ = self.events.send(PresenceEvent::Joined(peer.clone()));
+                    self.peers.insert(id, (peer, PeerStatus { last_seen: now, rtt, missed_rounds: 0 }));
+                }
+            }
+        }
+
+        let events = &self.events;
+        self.peers.retain(|id, (peer, status)| {
+            if seen_ids.contains(id) {
+                return true;
+            }
+            status.missed_rounds += 1;
+            if status.missed_rounds >= MAX_MISSED_ROUNDS {
+                let _ = events.send(PresenceEvent::Left(peer.clone()));
+                return false;
+            }
+            true
+        });
+    }
+
+    // The peer said goodbye (mDNS), no need to wait for it to expire
+    pub fn remove(&mut self, peer: &PingResponse) {
+        if let Some((peer, _)) = self.peers.remove(&peer.peer_id()) {
+            let _ = self.events.send(PresenceEvent::Left(peer));
+        }
+    }
+
+    pub fn peers(&self) -> HashSet<PingResponse> {
+        self.peers.values().map(|(peer, _)| peer.clone()).collect()
+    }
+
+    pub fn status(&self, peer: &PingResponse) -> Option<&PeerStatus> {
+        self.peers.get(&peer.peer_id()).map(|(_, status)| status)
+    }
+}
@@ -0,0 +1,881 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use multiset::HashMultiSet;
+use serde::{Deserialize, Serialize};
+
+use crate::common::{self, counter, delta, manifest, sparse, xattrs};
+use crate::common::manifest::{EntryKind, FileEntry};
+use crate::common::xattrs::Xattrs;
+use crate::common::metadata::FileMetadata;
+use crate::common::hash::hash_file_sha256;
+use crate::common::transfer_state::TransferError;
+use crate::engine::events::{EventSender, ProgressReporter};
+use crate::common::identity::Identity;
+use crate::networking::client::PingResponse;
+use std::net::UdpSocket;
+use std::sync::{Arc, Mutex};
+use std::collections::HashSet;
+use crate::networking::{client, discovery, sockets};
+use crate::networking::contacts::Contacts;
+use crate::networking::favorites::Favorites;
+use crate::networking::error::{self, NetError};
+use crate::networking::ports::{InfoSockets, Ports};
+
+
+
+#[derive(Default)]
+pub struct ServerControlData {
+    pub data_threads: BTreeMap<u32, (String, std::thread::JoinHandle<()>)>,
+    pub accepted_files: Arc<Mutex<HashMap<String, HashMultiSet<String>>>>,
+    // Duplicates in a batch, waiting for the first file with the same content to arrive
+    pub pending_copies: HashMap<[u8; 32], Vec<PendingCopy>>,
+    // Extended attributes from the manifest, keyed by peer IP and file name
+    pub entry_xattrs: HashMap<(String, String), Xattrs>,
+    // Request each accepted file belongs to, keyed the same way
+    pub batches: HashMap<(String, String), u32>,
+    pub settings: Arc<Mutex<ReceiverSettings>>,
+    pub favorites: Arc<Mutex<Favorites>>,
+    pub contacts: Arc<Mutex<Contacts>>,
+}
+
+// Different requests can wait for the same content, each copy is reported in its own
+pub struct PendingCopy {
+    pub dest: PathBuf,
+    pub peer: std::net::SocketAddr,
+    pub batch: u32,
+    pub attrs: CopyAttributes,
+}
+
+// Restored on a file copied locally, from its entry in the request. The received ones get them in the data header
+#[derive(Clone, Default)]
+pub struct CopyAttributes {
+    pub xattrs: Xattrs,
+    pub metadata: Option<FileMetadata>,
+    // See ReceiverSettings::applies_permissions_from
+    pub apply_permissions: bool,
+}
+
+#[derive(Default)]
+pub struct ReceiverSettings {
+    // Absolute, every received file is joined to it. See common::config::receive_dir
+    pub receive_dir: PathBuf,
+    // When set, permission bits are only applied for peers in trusted_peers
+    pub ignore_untrusted_permissions: bool,
+    pub trusted_peers: HashSet<String>,
+    pub symlink_policy: SymlinkPolicy,
+    pub visibility: Visibility,
+}
+
+// Saved in common::config like the visibility
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    Refuse,
+    // Only links whose target stays inside the receive directory
+    #[default]
+    InsideDestination,
+}
+
+// Who gets an answer to the discovery ping, saved in common::config
+#[derive(Default, Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    #[default]
+    Visible,
+    // Only pings signed by a paired device, see networking::contacts
+    ContactsOnly,
+    // No answers at all, only favorites may connect
+    Hidden,
+}
+
+impl ReceiverSettings {
+    pub fn applies_permissions_from(&self, ip: &str) -> bool {
+        !self.ignore_untrusted_permissions || self.trusted_peers.contains(ip)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct RequestData {
+    pub from: PingResponse,
+    pub files: Vec<FileEntry>,
+}
+
+// Hidden receivers only talk to their favorites
+fn refuses_connection(settings: &Arc<Mutex<ReceiverSettings>>, favorites: &Arc<Mutex<Favorites>>, ip: std::net::IpAddr) -> bool {
+    if settings.lock().unwrap().visibility != Visibility::Hidden || ip.is_loopback() {
+        return false;
+    }
+    // Favorites may need a DNS lookup, don't hold the lock meanwhile
+    let favorites = favorites.lock().unwrap().clone();
+    !favorites.allows(ip)
+}
+
+const PEER_INFO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+// How long a sender has to send its whole request once connected
+const REQUEST_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+// A data connection silent for this long is given up, a peer that stops sending would keep the thread and the file forever
+const DATA_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+// How long the sender has to open the next data connection, after the answer or the last one
+const DATA_CONNECTION_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+// How long the user has to answer a request, the sender waits a bit longer (client::ANSWER_TIMEOUT)
+const ANSWER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+// How often a request waiting for its answer checks that the sender is still there
+const SENDER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+pub fn control_connection(listener: std::net::TcpListener, events: EventSender, control_data: Arc<Mutex<ServerControlData>>, responders: Arc<Mutex<HashSet<client::PingResponse>>>) -> Result<(), NetError> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if error::is_transient(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        // See common::manifest for the request and response format
+
+        // Get peer info from ping response
+        let peer_addr = match stream.peer_addr() {
+            Ok(peer_addr) => sockets::normalize(peer_addr),
+            Err(e) => {
+                eprintln!("Cannot get the peer address: {}", e);
+                continue;
+            }
+        };
+
+        // One thread per connection, a peer slow to send its request never holds up the next one
+        std::thread::spawn({
+            let events = events.clone();
+            let control_data = Arc::clone(&control_data);
+            let responders = responders.clone();
+            move || request_connection(stream, peer_addr, events, control_data, responders)
+        });
+    }
+
+    Ok(())
+}
+
+// Reads one request, waits for its answer and sends it, then waits for the data of the accepted files
+fn request_connection(mut stream: std::net::TcpStream, peer_addr: std::net::SocketAddr, events: EventSender, control_data: Arc<Mutex<ServerControlData>>, responders: Arc<Mutex<HashSet<client::PingResponse>>>) {
+    let (settings, favorites) = {
+        let control_data = control_data.lock().unwrap();
+        (control_data.settings.clone(), control_data.favorites.clone())
+    };
+
+    if refuses_connection(&settings, &favorites, peer_addr.ip()) {
+        eprintln!("Hidden, refusing connection from {}", peer_addr);
+        return;
+    }
+
+    let mut peer_info: Option<PingResponse> = None;
+
+    // Sent from this machine through the loopback target, we are never in our own responders
+    if peer_addr.ip().is_loopback() {
+        peer_info = Some(PingResponse::new(peer_addr, std::env::consts::OS.to_string(), whoami::username(), String::from("loopback")));
+    }
+
+    // Wait until the peer info is available in responders.
+    // Peers added by hand on the other side may never show up here, after a while use the bare address
+    let wait_start = std::time::Instant::now();
+    while peer_info.is_none() && wait_start.elapsed() < PEER_INFO_TIMEOUT {
+        let responders_guard = responders.lock().unwrap();
+        for resp in responders_guard.iter() {
+            if resp.addr.ip() == peer_addr.ip() { // Compare only the IP, both are normalized
+                peer_info = Some(resp.clone());
+                break;
+            }
+        }
+        drop(responders_guard);
+        if peer_info.is_none() {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    let peer_info = peer_info.unwrap_or_else(|| PingResponse::new(peer_addr, String::new(), peer_addr.ip().to_string(), String::new()));
+
+    // A peer that connects and says nothing would keep this thread forever
+    if let Err(e) = stream.set_read_timeout(Some(REQUEST_READ_TIMEOUT)) {
+        eprintln!("Cannot set the read timeout for {}: {}", peer_addr, e);
+        return;
+    }
+    let mut reader = std::io::BufReader::new(&stream);
+    let files = match manifest::read_request(&mut reader) {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("Cannot read request from {}: {}", peer_addr, e);
+            return;
+        }
+    };
+
+    // Nothing to ask the user, e.g. a reachability probe that connected and closed
+    if files.is_empty() {
+        let _ = stream.write_all(manifest::encode_response(&[], &[]).as_bytes());
+        return;
+    }
+
+    let request_data = RequestData {
+        from: peer_info.clone(),
+        files: files.clone(),
+    };
+
+    // Answered with engine::events::Command::AnswerRequest
+    let id = counter::get_inc();
+    let answer = events.request(id, request_data);
+
+    let Some(accepted_files) = wait_for_answer(&answer, &stream) else {
+        eprintln!("{} went away before the request was answered", peer_addr);
+        events.withdraw(id);
+        return;
+    };
+
+    if accepted_files.is_empty() {
+        eprintln!("No files accepted by the user.");
+        let response = "REJECT\n";
+        if let Err(e) = stream.write_all(response.as_bytes()) {
+            eprintln!("Cannot send the response to {}: {}", peer_addr, e);
+        }
+        return;
+    }
+
+    // Read once, the whole request goes to the same directory
+    let (symlink_policy, receive_dir, apply_permissions) = {
+        let settings = settings.lock().unwrap();
+        (settings.symlink_policy, settings.receive_dir.clone(), settings.applies_permissions_from(&peer_addr.ip().to_string()))
+    };
+    let copy_attributes = |entry: &FileEntry| CopyAttributes { xattrs: entry.xattrs.clone(), metadata: entry.metadata, apply_permissions };
+
+    // Files whose content we already have are copied locally instead of being sent
+    let mut local_files = LocalFiles::scan(&receive_dir);
+    let mut sources: HashMap<[u8; 32], CopySource> = HashMap::new();
+    let mut send = Vec::new();
+    let mut have = Vec::new();
+
+    let mut links = Vec::new();
+
+    for file in accepted_files.iter() {
+        let Some(entry) = files.iter().find(|f| &f.name == file) else {
+            continue;
+        };
+
+        if let EntryKind::Symlink(target) = &entry.kind {
+            // Symlinks carry no data, they are created right away
+            if create_symlink(&events, target, &receive_dir.join(file), peer_addr, symlink_policy, &copy_attributes(entry), Some(id)) {
+                links.push(file.clone());
+            }
+        } else if let Some(source) = sources.get(&entry.hash) {
+            have.push((file.clone(), source.clone(), copy_attributes(entry)));
+        } else if let Some(path) = local_files.find(entry.size, &entry.hash) {
+            sources.insert(entry.hash, CopySource::Local(path.clone()));
+            have.push((file.clone(), CopySource::Local(path), copy_attributes(entry)));
+        } else {
+            sources.insert(entry.hash, CopySource::Batch(entry.hash));
+            send.push(file.clone());
+        }
+    }
+
+    // Registered before answering, the sender connects for the data right away
+    let peer_ip = peer_info.addr.ip().to_string();
+    {
+        let mut control_data_guard = control_data.lock().unwrap();
+        for file in send.iter() {
+            control_data_guard.batches.insert((peer_ip.clone(), file.clone()), id);
+            if let Some(entry) = files.iter().find(|f| &f.name == file)
+                && !entry.xattrs.is_empty()
+            {
+                control_data_guard.entry_xattrs.insert((peer_ip.clone(), file.clone()), entry.xattrs.clone());
+            }
+            control_data_guard.accepted_files.lock().unwrap().entry(peer_ip.clone()).or_insert_with(HashMultiSet::new).insert(file.clone());
+        }
+        // Copied as soon as the first file with this content is verified, which can happen right after the answer
+        for (file, source, attrs) in have.iter() {
+            if let CopySource::Batch(hash) = source {
+                control_data_guard.pending_copies.entry(*hash).or_default().push(PendingCopy { dest: receive_dir.join(file), peer: peer_addr, batch: id, attrs: attrs.clone() });
+            }
+        }
+    }
+
+    // Accepted and already here, copied even when the sender is gone. Done before answering: once answered
+    // the sender streams the other files, and a source with the name of one of them would be rewritten under the copy
+    for (file, source, attrs) in have.iter() {
+        if let CopySource::Local(path) = source
+            && let Some(hash) = files.iter().find(|f| &f.name == file).map(|f| &f.hash)
+        {
+            local_copy(&events, path, &receive_dir.join(file), peer_addr, attrs, hash, Some(id));
+        }
+    }
+
+    // Send the accepted files back to the client
+    let mut have_names = have.iter().map(|(name, _, _)| name.clone()).collect::<Vec<_>>();
+    have_names.extend(links);
+    let response = manifest::encode_response(&send, &have_names);
+    let request = AcceptedRequest { id, peer_addr, peer_ip, receive_dir, files, send, have };
+    let answered = match stream.write_all(response.as_bytes()) {
+        Ok(()) => true,
+        Err(e) => {
+            // The sender won't connect for the files, nothing to wait for
+            eprintln!("Cannot send the response to {}: {}", peer_addr, e);
+            request.fail_unclaimed(&events, &control_data, TransferError::from(e));
+            false
+        }
+    };
+    // The sender reads the answer until the connection is closed
+    drop(stream);
+
+    // Every accepted file ends as a finished transfer, also when its data never comes
+    // (the sender died, the data header could not be read)
+    if answered {
+        request.wait_for_data(&control_data);
+        request.fail_unclaimed(&events, &control_data, TransferError::Timeout);
+    }
+}
+
+// None when the sender closed the connection or stopped waiting. A request the engine dropped counts as rejected
+fn wait_for_answer(answer: &std::sync::mpsc::Receiver<Vec<String>>, stream: &std::net::TcpStream) -> Option<Vec<String>> {
+    let start = std::time::Instant::now();
+    while start.elapsed() < ANSWER_TIMEOUT {
+        match answer.recv_timeout(SENDER_CHECK_INTERVAL) {
+            Ok(accepted_files) => return Some(accepted_files),
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return Some(Vec::new()),
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+        }
+        if sender_gone(stream) {
+            return None;
+        }
+    }
+    None
+}
+
+// Peeks without blocking. The sender writes nothing before the answer, the end of the connection is all there is to read
+fn sender_gone(stream: &std::net::TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+    let gone = match stream.peek(&mut [0u8; 1]) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    gone
+}
+
+#[derive(Clone)]
+enum CopySource {
+    Local(PathBuf),
+    Batch([u8; 32]),
+}
+
+// What was answered to one request, see control_connection
+struct AcceptedRequest {
+    id: u32,
+    peer_addr: std::net::SocketAddr,
+    peer_ip: String,
+    receive_dir: PathBuf,
+    files: Vec<FileEntry>,
+    // Sent over a data connection
+    send: Vec<String>,
+    // Copied locally, from a file already here or from one in `send`
+    have: Vec<(String, CopySource, CopyAttributes)>,
+}
+
+impl AcceptedRequest {
+    // Files no data connection claimed yet
+    fn unclaimed(&self, control_data: &Arc<Mutex<ServerControlData>>) -> usize {
+        let control_data_guard = control_data.lock().unwrap();
+        self.send.iter()
+            .filter(|file| control_data_guard.batches.get(&(self.peer_ip.clone(), (*file).clone())) == Some(&self.id))
+            .count()
+    }
+
+    // Until every file was claimed or the sender stopped opening data connections.
+    // A large batch takes a while, the deadline starts again with every claimed file
+    fn wait_for_data(&self, control_data: &Arc<Mutex<ServerControlData>>) {
+        let mut unclaimed = self.unclaimed(control_data);
+        let mut deadline = std::time::Instant::now() + DATA_CONNECTION_TIMEOUT;
+
+        while unclaimed > 0 && std::time::Instant::now() < deadline {
+            std::thread::sleep(SENDER_CHECK_INTERVAL.min(deadline.saturating_duration_since(std::time::Instant::now())));
+            let now_unclaimed = self.unclaimed(control_data);
+            if now_unclaimed < unclaimed {
+                deadline = std::time::Instant::now() + DATA_CONNECTION_TIMEOUT;
+            }
+            unclaimed = now_unclaimed;
+        }
+    }
+
+    // Fails the files no data connection claimed, and their duplicates waiting in pending_copies
+    fn fail_unclaimed(&self, events: &EventSender, control_data: &Arc<Mutex<ServerControlData>>, reason: TransferError) {
+        let size_of = |name: &str| self.files.iter().find(|f| f.name == name).map(|f| f.size).unwrap_or(0);
+        let mut failed: Vec<(PathBuf, u64)> = Vec::new();
+        let mut lost_hashes = HashSet::new();
+
+        let mut control_data_guard = control_data.lock().unwrap();
+        for file in self.send.iter() {
+            // Claimed files have already left `batches`, see receive_file
+            let key = (self.peer_ip.clone(), file.clone());
+            if control_data_guard.batches.get(&key) != Some(&self.id) {
+                continue;
+            }
+            control_data_guard.batches.remove(&key);
+            control_data_guard.entry_xattrs.remove(&key);
+            if let Some(accepted) = control_data_guard.accepted_files.lock().unwrap().get_mut(&self.peer_ip) {
+                accepted.remove(file);
+            }
+            if let Some(entry) = self.files.iter().find(|f| &f.name == file) {
+                lost_hashes.insert(entry.hash);
+            }
+            failed.push((self.receive_dir.join(file), size_of(file)));
+        }
+
+        for (file, source, _) in self.have.iter() {
+            let CopySource::Batch(hash) = source else {
+                continue;
+            };
+            let dest = self.receive_dir.join(file);
+            if let Some(copies) = control_data_guard.pending_copies.get_mut(hash).filter(|_| lost_hashes.contains(hash)) {
+                copies.retain(|pending| !(pending.dest == dest && pending.batch == self.id));
+                failed.push((dest, size_of(file)));
+            }
+        }
+        control_data_guard.pending_copies.retain(|_, copies| !copies.is_empty());
+        drop(control_data_guard);
+
+        for (dest, size) in failed {
+            eprintln!("{} never arrived from {}", dest.display(), self.peer_addr);
+            let mut state = common::transfer_state::TransferState::new(common::transfer_state::TransferType::Error(reason.clone()), String::new(), dest.to_string_lossy().into_owned(), self.peer_addr, size);
+            state.log("The sender did not send the data");
+            state.batch = Some(self.id);
+            events.add(counter::get_inc(), state);
+        }
+    }
+}
+
+// Files in the receive directory grouped by size, hashes are only computed when needed
+struct LocalFiles {
+    by_size: HashMap<u64, Vec<PathBuf>>,
+    hashes: HashMap<PathBuf, [u8; 32]>,
+}
+
+impl LocalFiles {
+    fn scan(dir: &Path) -> Self {
+        let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_some_and(|ext| ext == "part") {
+                    continue;
+                }
+                if let Ok(metadata) = entry.metadata()
+                    && metadata.is_file()
+                {
+                    by_size.entry(metadata.len()).or_default().push(path);
+                }
+            }
+        }
+
+        LocalFiles { by_size, hashes: HashMap::new() }
+    }
+
+    fn find(&mut self, size: u64, hash: &[u8; 32]) -> Option<PathBuf> {
+        for path in self.by_size.get(&size)? {
+            let local_hash = match self.hashes.get(path) {
+                Some(local_hash) => *local_hash,
+                None => {
+                    let Ok(local_hash) = hash_file_sha256(path) else {
+                        continue;
+                    };
+                    self.hashes.insert(path.clone(), local_hash);
+                    local_hash
+                }
+            };
+
+            if &local_hash == hash {
+                return Some(path.clone());
+            }
+        }
+        None
+    }
+}
+
+// The copy is hashed again, the source may have changed since it was found
+fn local_copy(events: &EventSender, source: &Path, dest: &Path, peer: std::net::SocketAddr, attrs: &CopyAttributes, hash: &[u8; 32], batch: Option<u32>) {
+    // The file may already be there with the same name
+    let same_file = source == dest;
+
+    let copied = if same_file {
+        Ok(())
+    } else {
+        File::open(source)
+            .and_then(|mut source| std::io::copy(&mut source, &mut create_file(dest)?))
+            .map_err(TransferError::from)
+            .and_then(|_| match hash_file_sha256(dest) {
+                Ok(copied_hash) if &copied_hash == hash => Ok(()),
+                Ok(_) => Err(TransferError::HashMismatch),
+                Err(e) => Err(e.into()),
+            })
+    };
+    let ttype = match copied {
+        Ok(()) => {
+            xattrs::apply(dest, &attrs.xattrs, attrs.apply_permissions);
+            if let Some(metadata) = &attrs.metadata
+                && let Err(e) = metadata.apply(dest, attrs.apply_permissions)
+            {
+                eprintln!("Cannot restore metadata of {}: {}", dest.display(), e);
+            }
+            common::transfer_state::TransferType::CompletelyReceived
+        }
+        Err(e) => {
+            eprintln!("Cannot copy {} to {}: {}", source.display(), dest.display(), e);
+            common::transfer_state::TransferType::Error(e)
+        }
+    };
+
+    let size = std::fs::metadata(source).map(|m| m.len()).unwrap_or(0);
+    let mut state = common::transfer_state::TransferState::new(ttype, source.to_string_lossy().into_owned(), dest.to_string_lossy().into_owned(), peer, size);
+    state.log("Copied locally, the content was already here");
+    state.batch = batch;
+    events.add(counter::get_inc(), state);
+}
+
+fn create_symlink(events: &EventSender, target: &str, dest: &Path, peer: std::net::SocketAddr, policy: SymlinkPolicy, attrs: &CopyAttributes, batch: Option<u32>) -> bool {
+    let allowed = match policy {
+        SymlinkPolicy::Refuse => false,
+        SymlinkPolicy::InsideDestination => manifest::symlink_stays_inside(target),
+    };
+
+    let created = if allowed {
+        make_symlink(target, dest).map_err(|e| {
+            eprintln!("Cannot create symlink {} -> {}: {}", dest.display(), target, e);
+            TransferError::from(e)
+        })
+    } else {
+        eprintln!("Refusing symlink {} -> {}", dest.display(), target);
+        Err(TransferError::Rejected)
+    };
+
+    if created.is_ok() {
+        xattrs::apply(dest, &attrs.xattrs, attrs.apply_permissions);
+    }
+
+    let ttype = match &created {
+        Ok(()) => common::transfer_state::TransferType::CompletelyReceived,
+        Err(reason) => common::transfer_state::TransferType::Error(reason.clone()),
+    };
+    let mut state = common::transfer_state::TransferState::new(ttype, target.to_string(), dest.to_string_lossy().into_owned(), peer, 0);
+    state.batch = batch;
+    events.add(counter::get_inc(), state);
+
+    created.is_ok()
+}
+
+// Received data never goes through a symlink already at that name, it could point anywhere
+fn create_file(path: &Path) -> std::io::Result<File> {
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink()) {
+        return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} is a symlink", path.display())));
+    }
+    let mut options = File::options();
+    options.write(true).create(true).truncate(true);
+    // Also when the link shows up after the check
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.custom_flags(libc::O_NOFOLLOW);
+    }
+    options.open(path)
+}
+
+#[cfg(unix)]
+fn make_symlink(target: &str, dest: &Path) -> std::io::Result<()> {
+    // Replace an older link with the same name, never a regular file
+    if std::fs::symlink_metadata(dest).is_ok_and(|m| m.file_type().is_symlink()) {
+        std::fs::remove_file(dest)?;
+    }
+    std::os::unix::fs::symlink(target, dest)
+}
+
+#[cfg(not(unix))]
+fn make_symlink(_target: &str, _dest: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "Symlinks are not supported on this platform"))
+}
+
+pub fn data_connection(listener: std::net::TcpListener, events: EventSender, control_data_: Arc<Mutex<ServerControlData>>) -> Result<(), NetError> {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) if error::is_transient(&e) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let peer_addr = match stream.peer_addr() {
+            Ok(peer_addr) => sockets::normalize(peer_addr),
+            Err(e) => {
+                eprintln!("Error getting peer address: {}", e);
+                continue;
+            }
+        };
+        let from_ip = peer_addr.ip().to_string();
+
+        // Files are registered as accepted before the sender gets the answer, see receive_file
+        let events = events.clone();
+        let control_data = Arc::clone(&control_data_);
+        
+        let key = counter::get_inc();
+
+        // Held until the thread is registered, the thread removes itself when done
+        let mut control_guard = control_data_.lock().unwrap();
+        let thread_join_handle = std::thread::spawn(move || {
+            if let Err(e) = receive_file(stream, peer_addr, key, &from_ip, &events, &control_data) {
+                eprintln!("Cannot receive from {}: {}", peer_addr, e);
+                control_data.lock().unwrap().data_threads.remove(&key);
+            }
+        });
+        control_guard.data_threads.insert(key, (String::new(), thread_join_handle));
+    }
+
+    Ok(())
+}
+
+// One file on a data connection, the header is read here
+fn receive_file(mut stream: std::net::TcpStream, peer_addr: std::net::SocketAddr, key: u32, from_ip: &str, events: &EventSender, control_data: &Arc<Mutex<ServerControlData>>) -> Result<(), NetError> {
+    let mut file = [0u8; 256];
+    let mut file_size = [0u8; 8];
+    let mut hash = [0u8; 32];
+    let mut file_metadata = [0u8; FileMetadata::SIZE];
+
+    // An expired read ends up as TransferError::Timeout
+    stream.set_read_timeout(Some(DATA_READ_TIMEOUT))?;
+
+    // Read the file name, size, hash and metadata from the stream (256 + 8 + 32 + 28 bytes)
+    stream.read_exact(&mut file)?;
+    stream.read_exact(&mut file_size)?;
+    stream.read_exact(&mut hash)?;
+    stream.read_exact(&mut file_metadata)?;
+    let file_metadata = FileMetadata::from_bytes(&file_metadata);
+    let file_name = String::from_utf8_lossy(&file).trim_end_matches('\0').to_string();
+    if file_name.is_empty() {
+        return Err(NetError::Protocol(String::from("data header without a file name")));
+    }
+
+    let file_size = u64::from_le_bytes(file_size);
+
+    // The control data is only locked briefly, other files keep arriving meanwhile.
+    // Each accepted copy of a name admits one data connection
+    let mut control_guard = control_data.lock().unwrap();
+    let claimed = control_guard.accepted_files.lock().unwrap().get_mut(from_ip).is_some_and(|files| files.remove(&file_name));
+
+    if !claimed {
+        eprintln!("File {} not accepted", file_name);
+
+        control_guard.data_threads.remove(&key);
+
+        let _ = stream.write_all(b"REJECT\n");
+
+        return Ok(());
+    }
+
+    let batch = control_guard.batches.remove(&(from_ip.to_string(), file_name.clone()));
+    let attrs = control_guard.entry_xattrs.remove(&(from_ip.to_string(), file_name.clone()));
+    let (apply_permissions, receive_dir) = {
+        let settings = control_guard.settings.lock().unwrap();
+        (settings.applies_permissions_from(from_ip), settings.receive_dir.clone())
+    };
+    let path = receive_dir.join(&file_name);
+
+    if let Some(data_thread) = control_guard.data_threads.get_mut(&key) {
+        data_thread.0 = file_name.clone();
+    }
+
+    drop(control_guard);
+
+    // If we already have an older version of the file, ask for a delta instead of the whole file
+    let use_delta = std::fs::symlink_metadata(&path)
+        .map(|m| m.is_file() && m.len() >= delta::DELTA_MIN_SIZE)
+        .unwrap_or(false);
+
+    let mut transfer_state = common::transfer_state::TransferState::new(common::transfer_state::TransferType::Receiving, String::new(), path.to_string_lossy().into_owned(), peer_addr, file_size);
+    if use_delta {
+        transfer_state.log("An older version is here, receiving only the changes");
+    }
+    transfer_state.batch = batch;
+    transfer_state.hash = Some(hash);
+    
+    events.add(key, transfer_state);
+
+    let received_path = if use_delta {
+        receive_dir.join(format!("{}.part", file_name))
+    } else {
+        path.clone()
+    };
+
+    let mut progress = ProgressReporter::new(events.clone(), key);
+
+    // A failure here leaves a broken file behind
+    let transfer_result = if use_delta {
+        delta::compute_signature(&path).and_then(|signature| {
+            stream.write_all(b"DELTA\n")?;
+            delta::write_signature(&mut std::io::BufWriter::new(&stream), &signature)?;
+
+            let mut basis = File::open(&path)?;
+            let mut output_file = std::io::BufWriter::new(create_file(&received_path)?);
+
+            delta::apply_delta(&mut stream, &mut basis, &signature, &mut output_file, |bytes| progress.report(bytes)).map(|_| ())
+        })
+    } else {
+        let chunk_size = common::config::current().chunk_size;
+        let mut buffer = vec![0u8; chunk_size];
+
+        stream.write_all(b"ACCEPT\n")
+            .and_then(|_| create_file(&received_path))
+            .and_then(|mut output_file| {
+                // Everything outside the extents stays a hole
+                let extents = sparse::read_extent_map(&mut stream, file_size)?;
+                output_file.set_len(file_size)?;
+
+                for (offset, len) in extents {
+                    output_file.seek(SeekFrom::Start(offset))?;
+
+                    let mut received: u64 = 0;
+                    while received < len {
+                        let to_read = (len - received).min(chunk_size as u64) as usize;
+                        let n = stream.read(&mut buffer[..to_read])?;
+                        if n == 0 {
+                            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed by the client"));
+                        }
+
+                        output_file.write_all(&buffer[..n])?;
+                        received += n as u64;
+
+                        progress.report(offset + received);
+                    }
+                }
+                Ok(())
+            })
+    };
+
+    progress.flush();
+
+    let result = match transfer_result {
+        Err(e) => {
+            eprintln!("Error while receiving {}: {}", file_name, e);
+            if use_delta {
+                let _ = std::fs::remove_file(&received_path);
+            }
+            Err(TransferError::from(e))
+        }
+        Ok(()) => {
+            events.set_type(key, common::transfer_state::TransferType::VerifyingHash);
+
+            let received_file_hash = hash_file_sha256(&received_path).unwrap_or([0u8; 32]);
+
+            if received_file_hash != hash {
+                if use_delta {
+                    // Keep the old version untouched
+                    let _ = std::fs::remove_file(&received_path);
+                }
+                Err(TransferError::HashMismatch)
+            } else if use_delta {
+                std::fs::rename(&received_path, &path).map_err(TransferError::from)
+            } else {
+                Ok(())
+            }
+        }
+    };
+
+    let verified = result.is_ok();
+
+    // Tells the sender how it ended, it may already be gone
+    let verdict = match &result {
+        Ok(()) => "VERIFIED\n",
+        Err(TransferError::HashMismatch) => "MISMATCH\n",
+        Err(_) => "FAILED\n",
+    };
+    let _ = stream.write_all(verdict.as_bytes());
+
+    match &result {
+        Ok(()) => events.set_type(key, common::transfer_state::TransferType::CompletelyReceived),
+        Err(reason) => events.set_type(key, common::transfer_state::TransferType::Error(reason.clone())),
+    }
+
+    if verified {
+        if let Some(attrs) = attrs {
+            xattrs::apply(&path, &attrs, apply_permissions);
+        }
+
+        if let Err(e) = file_metadata.apply(&path, apply_permissions) {
+            eprintln!("Cannot restore metadata of {}: {}", file_name, e);
+        }
+    }
+
+    // Any verified file serves every copy of its content, a failed one only fails the copies of its own request
+    let mut control_guard = control_data.lock().unwrap();
+    let mut copies = control_guard.pending_copies.remove(&hash).unwrap_or_default();
+    if result.is_err() {
+        let (own, others): (Vec<_>, Vec<_>) = copies.into_iter().partition(|copy| Some(copy.batch) == batch);
+        if !others.is_empty() {
+            control_guard.pending_copies.insert(hash, others);
+        }
+        copies = own;
+    }
+    control_guard.data_threads.remove(&key);
+    drop(control_guard);
+
+    for copy in copies {
+        match &result {
+            Ok(()) => local_copy(events, &path, &copy.dest, copy.peer, &copy.attrs, &hash, Some(copy.batch)),
+            Err(reason) => {
+                let mut state = common::transfer_state::TransferState::new(common::transfer_state::TransferType::Error(reason.clone()), path.to_string_lossy().into_owned(), copy.dest.to_string_lossy().into_owned(), copy.peer, file_size);
+                state.log(format!("Not copied, receiving {} failed", file_name));
+                state.batch = Some(copy.batch);
+                events.add(counter::get_inc(), state);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Answers one ping, if any arrives before the read timeout
+fn answer_ping(socket: &UdpSocket, ports: &Ports, identity: &Arc<Mutex<Identity>>, settings: &Arc<Mutex<ReceiverSettings>>, contacts: &Arc<Mutex<Contacts>>) -> Result<(), NetError> {
+    let mut buf = [0u8; 1024];
+    let (n, src) = match socket.recv_from(&mut buf) {
+        Ok(received) => received,
+        // Timeouts, and on Windows the ICMP errors caused by earlier replies
+        Err(e) if error::is_transient(&e) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let answer = match settings.lock().unwrap().visibility {
+        Visibility::Visible => true,
+        Visibility::ContactsOnly => discovery::verify_ping(&buf[..n], &contacts.lock().unwrap()),
+        Visibility::Hidden => false,
+    };
+    if !answer {
+        return Ok(());
+    }
+
+    // Built every time, the display name can change while running
+    let response = discovery::ping_reply(&identity.lock().unwrap(), ports);
+    if let Err(e) = socket.send_to(response.as_bytes(), src) {
+        eprintln!("Errore nell'invio della risposta: {}", e);
+    }
+    Ok(())
+}
+
+// `ports` are the bound ones, they are advertised in the reply
+pub fn info_socket(sockets: InfoSockets, ports: Ports, identity: Arc<Mutex<Identity>>, settings: Arc<Mutex<ReceiverSettings>>, contacts: Arc<Mutex<Contacts>>) -> Result<(), NetError> {
+    eprintln!("UDP socket in ascolto su {:?}", sockets.v4.local_addr());
+
+    // Both sockets are served by this thread, the short timeout keeps them taking turns.
+    // IPv6 pings arrive on the link-local all nodes group, no need to join it
+    let all_sockets: Vec<&UdpSocket> = std::iter::once(&sockets.v4).chain(sockets.v6.as_ref()).collect();
+    for socket in all_sockets.iter() {
+        socket.set_read_timeout(Some(std::time::Duration::from_millis(50)))?;
+    }
+
+    loop {
+        for socket in all_sockets.iter() {
+            answer_ping(socket, &ports, &identity, &settings, &contacts)?;
+        }
+    }
+}
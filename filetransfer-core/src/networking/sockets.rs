@@ -0,0 +1,89 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, UdpSocket};
+
+use socket2::{Domain, Protocol, Socket, Type};
+
+// IPv4 peers connecting to a dual-stack socket show up as ::ffff:a.b.c.d.
+// Other IPv6 addresses keep their scope id, link-local peers can't be reached without it
+pub fn normalize(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(v4) => SocketAddr::new(IpAddr::V4(v4), v6.port()),
+            None => SocketAddr::V6(v6),
+        },
+        SocketAddr::V4(_) => addr,
+    }
+}
+
+pub fn normalize_ip(ip: IpAddr) -> IpAddr {
+    ip.to_canonical()
+}
+
+fn dual_stack_socket(port: u16, ty: Type, protocol: Protocol) -> std::io::Result<Socket> {
+    let socket = Socket::new(Domain::IPV6, ty, Some(protocol))?;
+    // Windows and some BSDs are v6 only by default
+    socket.set_only_v6(false)?;
+    // A listener restarted right after a transfer would wait for TIME_WAIT to expire, like std does.
+    // Not on Windows, there it would let two instances share the port
+    #[cfg(unix)]
+    if ty == Type::STREAM {
+        socket.set_reuse_address(true)?;
+    }
+    socket.bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port).into())?;
+    Ok(socket)
+}
+
+// Listens on both IPv4 and IPv6, falls back to IPv4 only where IPv6 is disabled
+pub fn dual_stack_listener(port: u16) -> std::io::Result<TcpListener> {
+    match dual_stack_socket(port, Type::STREAM, Protocol::TCP) {
+        Ok(socket) => {
+            socket.listen(128)?;
+            Ok(socket.into())
+        }
+        Err(_) => TcpListener::bind(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)),
+    }
+}
+
+// Several instances on the same host can bind it, every one of them gets the broadcast pings
+fn set_shared(socket: &Socket) -> std::io::Result<()> {
+    socket.set_reuse_address(true)?;
+    // Linux and Windows only need SO_REUSEADDR, macOS and the BSDs want SO_REUSEPORT too
+    #[cfg(all(unix, not(target_os = "linux")))]
+    socket.set_reuse_port(true)?;
+    Ok(())
+}
+
+pub fn udp_v4(port: u16, shared: bool) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    if shared {
+        set_shared(&socket)?;
+    }
+    socket.bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port).into())?;
+    Ok(socket.into())
+}
+
+// IPv6 only UDP socket, used next to an IPv4 one bound to the same port
+pub fn udp_v6_only(port: u16, shared: bool) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_only_v6(true)?;
+    if shared {
+        set_shared(&socket)?;
+    }
+    socket.bind(&SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port).into())?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddrV6;
+
+    #[test]
+    fn normalize_unmaps_ipv4_and_keeps_the_scope_id() {
+        let mapped = SocketAddr::V6(SocketAddrV6::new(Ipv4Addr::new(192, 168, 1, 20).to_ipv6_mapped(), 24934, 0, 0));
+        assert_eq!(normalize(mapped), "192.168.1.20:24934".parse().unwrap());
+
+        let link_local = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 24934, 0, 3));
+        assert_eq!(normalize(link_local), link_local);
+        assert!(matches!(normalize(link_local), SocketAddr::V6(v6) if v6.scope_id() == 3));
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::notify::Notify;
+use crate::networking::error::NetError;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// A service that ran this long before failing starts again without waiting long
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServiceState {
+    Starting,
+    Running,
+    Offline(String),
+}
+
+impl fmt::Display for ServiceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServiceState::Starting => write!(f, "starting"),
+            ServiceState::Running => write!(f, "running"),
+            ServiceState::Offline(reason) => write!(f, "offline: {}", reason),
+        }
+    }
+}
+
+// State of every background service by name, shown in the GUI
+pub type ServiceStatus = Arc<Mutex<BTreeMap<&'static str, ServiceState>>>;
+
+fn set_state(status: &ServiceStatus, notify: &Notify, name: &'static str, state: ServiceState) {
+    if let ServiceState::Offline(reason) = &state {
+        eprintln!("{} offline: {}", name, reason);
+    }
+    status.lock().unwrap().insert(name, state);
+    notify();
+}
+
+/*
+Runs `run` until it fails or panics, then binds a new socket with `bind` and runs it again,
+waiting longer after every failure in a row. `first` is the socket bound at startup (see networking::ports).
+Blocks forever, call it from the service thread.
+*/
+pub fn supervise<S, B, R>(name: &'static str, status: ServiceStatus, notify: Notify, first: Result<S, NetError>, mut bind: B, mut run: R)
+where
+    B: FnMut() -> Result<S, NetError>,
+    R: FnMut(S) -> Result<(), NetError>,
+{
+    let mut next = Some(first);
+    let mut backoff = MIN_BACKOFF;
+
+    loop {
+        let socket = match next.take() {
+            Some(socket) => socket,
+            None => {
+                set_state(&status, &notify, name, ServiceState::Starting);
+                bind()
+            }
+        };
+
+        match socket {
+            Ok(socket) => {
+                set_state(&status, &notify, name, ServiceState::Running);
+                let started = Instant::now();
+
+                let reason = match panic::catch_unwind(AssertUnwindSafe(|| run(socket))) {
+                    Ok(Ok(())) => String::from("stopped"),
+                    Ok(Err(e)) => e.to_string(),
+                    Err(_) => String::from("crashed"),
+                };
+                set_state(&status, &notify, name, ServiceState::Offline(reason));
+
+                if started.elapsed() >= STABLE_RUN {
+                    backoff = MIN_BACKOFF;
+                }
+            }
+            Err(e) => set_state(&status, &notify, name, ServiceState::Offline(e.to_string())),
+        }
+
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
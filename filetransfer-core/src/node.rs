@@ -0,0 +1,346 @@
+use std::collections::HashSet;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::common::config::{self, Config};
+use crate::common::history;
+use crate::common::identity::{self, Identity};
+use crate::common::notify::Notify;
+use crate::engine::Engine;
+use crate::engine::events::{Command, Event};
+use crate::networking::{client, favorites, mdns, ports, server, supervisor};
+use crate::networking::client::PingResponse;
+use crate::networking::contacts::Contacts;
+use crate::networking::favorites::Favorites;
+use crate::networking::ports::{Listeners, Ports};
+use crate::networking::presence::{PresenceEvent, PresenceTable};
+use crate::networking::server::{ReceiverSettings, ServerControlData};
+use crate::networking::supervisor::ServiceStatus;
+
+// What a node does besides finding peers
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Role {
+    // Answers pings, announces itself and receives
+    Serve,
+    // Sends and keeps the history, nothing answers the requests sent to it so it doesn't announce itself
+    Send,
+    // Only finds peers
+    Discover,
+}
+
+// One running instance: the listeners, discovery and the engine
+pub struct Node {
+    engine: Engine,
+    role: Role,
+    server_control_data: Arc<Mutex<ServerControlData>>,
+    pub identity: Arc<Mutex<Identity>>,
+    // The bound ones, the requested ones when binding failed
+    pub ports: Ports,
+    pub responders: Arc<Mutex<HashSet<PingResponse>>>,
+    pub presence: Arc<Mutex<PresenceTable>>,
+    pub favorites: Arc<Mutex<Favorites>>,
+    pub contacts: Arc<Mutex<Contacts>>,
+    pub receiver_settings: Arc<Mutex<ReceiverSettings>>,
+    pub services: ServiceStatus,
+    // Shared with the config watcher, it announces the new display name
+    pub mdns: Option<Arc<mdns::Mdns>>,
+    // Called whenever peers, services or favorites change
+    pub notify: Notify,
+}
+
+impl Node {
+    // Binds the ports and starts every service, failures are reported in `services` and retried
+    pub fn start(ports: Ports, notify: Notify, presence_events: Sender<PresenceEvent>) -> Self {
+        Self::start_with(ports, notify, presence_events, Role::Serve)
+    }
+
+    // Sends files without receiving, for a one-off sender like `ft send`
+    pub fn start_sender(ports: Ports, notify: Notify, presence_events: Sender<PresenceEvent>) -> Self {
+        Self::start_with(ports, notify, presence_events, Role::Send)
+    }
+
+    // Only finds peers, for `ft peers` and a window attached to a daemon that receives and sends (see daemon)
+    pub fn start_discovery(info_port: u16, notify: Notify, presence_events: Sender<PresenceEvent>) -> Self {
+        let ports = Ports { info: info_port, ..Ports::ephemeral() };
+        Self::start_with(ports, notify, presence_events, Role::Discover)
+    }
+
+    fn start_with(ports: Ports, notify: Notify, presence_events: Sender<PresenceEvent>, role: Role) -> Self {
+        let serve = role == Role::Serve;
+        let server_control_data = ServerControlData {
+            favorites: Arc::new(Mutex::new(Favorites::load())),
+            contacts: Arc::new(Mutex::new(Contacts::load())),
+            ..Default::default()
+        };
+        let receiver_settings = server_control_data.settings.clone();
+        {
+            let config = config::current();
+            let mut settings = receiver_settings.lock().unwrap();
+            settings.receive_dir = config::receive_dir(&config);
+            settings.visibility = config.visibility;
+            apply_receiver_config(&config, &mut settings);
+        }
+        let visibility = receiver_settings.lock().unwrap().visibility;
+        let favorites = server_control_data.favorites.clone();
+        let contacts = server_control_data.contacts.clone();
+        let mut identity = Identity::load_or_create();
+        if let Some(name) = config::current().display_name {
+            set_display_name(&mut identity, &name);
+        }
+
+        let listeners = Listeners::bind(ports);
+        let ports = listeners.ports;
+
+        let responders = Arc::new(Mutex::new(HashSet::new()));
+        // Only the node doing the transfers keeps the history, a window attached to the daemon doesn't
+        let engine = if role != Role::Discover {
+            let responders: Arc<Mutex<HashSet<PingResponse>>> = Arc::clone(&responders);
+            let favorites = favorites.clone();
+            Engine::start(history::recorder(move |ip| {
+                let favorites = favorites.lock().unwrap().online.values().cloned().collect::<Vec<_>>();
+                responders.lock().unwrap().iter().cloned().chain(favorites).find(|peer| peer.addr.ip() == ip)
+            }))
+        } else {
+            Engine::start(|_| ())
+        };
+
+        let node = Node {
+            engine,
+            role,
+            server_control_data: Arc::new(Mutex::new(server_control_data)),
+            ports,
+            responders,
+            presence: Arc::new(Mutex::new(PresenceTable::new(presence_events))),
+            favorites,
+            contacts,
+            receiver_settings,
+            services: ServiceStatus::default(),
+            // The daemon announces this device. mDNS announces the name too, it only runs when visible to everyone
+            mdns: match mdns::Mdns::start(&mdns::instance_name(&identity), &identity, ports, serve && visibility == server::Visibility::Visible) {
+                Ok(mdns) => Some(Arc::new(mdns)),
+                Err(e) => {
+                    eprintln!("mDNS discovery disabled: {}", e);
+                    None
+                }
+            },
+            identity: Arc::new(Mutex::new(identity)),
+            notify,
+        };
+
+        node.start_threads(listeners, serve);
+
+        config::watch({
+            let identity = node.identity.clone();
+            // Only announced by the node that receives
+            let mdns = node.mdns.clone().filter(|_| serve);
+            let settings = node.receiver_settings.clone();
+            let notify = node.notify.clone();
+            move |previous, config| {
+                config_changed(previous, config, &identity, mdns.as_deref(), &settings);
+                notify();
+            }
+        });
+
+        node
+    }
+
+    pub fn send_files(&self, peer: PingResponse, files: Vec<String>) {
+        self.engine.send(Command::SendFiles { peer: Box::new(peer), files });
+    }
+
+    // An empty list rejects the request
+    pub fn accept_request(&self, id: u32, accepted_files: Vec<String>) {
+        self.engine.send(Command::AnswerRequest { id, accepted_files });
+    }
+
+    // See engine::Engine::subscribe
+    pub fn subscribe_events(&self) -> Receiver<Event> {
+        self.engine.subscribe()
+    }
+
+    // Discovered peers and the favorites that answered
+    pub fn peers(&self) -> Vec<PingResponse> {
+        let mut peers = self.responders.lock().unwrap().clone();
+        peers.extend(self.favorites.lock().unwrap().online.values().cloned());
+
+        let mut peers: Vec<PingResponse> = peers.into_iter().collect();
+        peers.sort_by(|a, b| a.name().cmp(b.name()).then(a.addr.cmp(&b.addr)));
+        peers
+    }
+
+    // By name, device ID or address. An address nobody announced is probed directly, like a favorite.
+    // Its own device ID gives a receiving node its loopback address, like "This device" in the GUI.
+    // Otherwise this node is never returned, sending to it would wait forever for an answer nobody gives
+    pub fn find_peer(&self, query: &str) -> Option<PingResponse> {
+        let identity = self.identity.lock().unwrap().clone();
+        if self.role == Role::Serve && identity.device_id == query {
+            return Some(PingResponse::loopback(&identity, &self.ports));
+        }
+
+        let found = self.peers().into_iter().find(|peer| {
+            peer.name().eq_ignore_ascii_case(query)
+                || peer.hostname.eq_ignore_ascii_case(query)
+                || peer.device_id == query
+                || peer.addr.to_string() == query
+                || peer.addr.ip().to_string() == query
+        });
+        if found.is_some() {
+            return found;
+        }
+
+        favorites::probe(query, &identity, self.ports.info)
+    }
+
+    // Saved right away and probed in the background, so it shows as online without waiting for the next round
+    pub fn add_favorite(&self, address: &str) {
+        let mut favorites = self.favorites.lock().unwrap();
+        if !favorites.add(address) {
+            return;
+        }
+        if let Err(e) = favorites.save() {
+            eprintln!("Cannot save the favorites: {}", e);
+        }
+        drop(favorites);
+
+        let favorites = self.favorites.clone();
+        let identity = self.identity.lock().unwrap().clone();
+        let notify = self.notify.clone();
+        let address = address.to_string();
+        let info_port = self.ports.info;
+        thread::spawn(move || {
+            if let Some(response) = favorites::probe(&address, &identity, info_port) {
+                let mut favorites = favorites.lock().unwrap();
+                if favorites.addresses.contains(&address) {
+                    favorites.online.insert(address, response);
+                }
+            }
+            notify();
+        });
+    }
+
+    // Every service restarts on its own when it fails, see networking::supervisor
+    // Without `serve` nothing answers pings nor accepts requests
+    fn start_threads(&self, listeners: Listeners, serve: bool) {
+        let Listeners { control, data, info, discovery, ports } = listeners;
+
+        let mdns_found = Arc::new(Mutex::new(HashSet::new()));
+
+        if let Some(mdns) = &self.mdns
+            && let Err(e) = mdns.browse(self.responders.clone(), self.presence.clone(), mdns_found.clone(), self.notify.clone())
+        {
+            eprintln!("Cannot browse mDNS services: {}", e);
+        }
+
+        thread::spawn({
+            let services = self.services.clone();
+            let mut responders = self.responders.clone();
+            let notify = self.notify.clone();
+            let presence = self.presence.clone();
+            let favorites = self.favorites.clone();
+            let identity = self.identity.lock().unwrap().clone();
+            move || supervisor::supervise("discovery", services, notify.clone(), discovery,
+                || ports::bind_discovery(ports.discovery, ports.info),
+                |sockets| client::info_socket(sockets, &mut responders, &identity, &presence, &favorites, &mdns_found, &notify))
+        });
+
+        thread::spawn({
+            let favorites = self.favorites.clone();
+            let identity = self.identity.lock().unwrap().clone();
+            let notify = self.notify.clone();
+            move || favorites::probe_loop(favorites, identity, ports.info, notify)
+        });
+
+        if !serve {
+            return;
+        }
+    
+        thread::spawn({
+            let services = self.services.clone();
+            let notify = self.notify.clone();
+            let identity = self.identity.clone();
+            let settings = self.receiver_settings.clone();
+            let contacts = self.contacts.clone();
+            move || supervisor::supervise("discovery responder", services, notify, info,
+                || ports::bind_info(ports.info),
+                |sockets| server::info_socket(sockets, ports, identity.clone(), settings.clone(), contacts.clone()))
+        });
+
+        thread::spawn({
+            let services = self.services.clone();
+            let notify = self.notify.clone();
+            let events = self.engine.events();
+            let server_control_data = self.server_control_data.clone();
+            let responders = self.responders.clone();
+            move || supervisor::supervise("receiver", services, notify, control,
+                || ports::bind_tcp(ports.control),
+                |listener| server::control_connection(listener, events.clone(), server_control_data.clone(), responders.clone()))
+        });
+
+        thread::spawn({
+            let services = self.services.clone();
+            let notify = self.notify.clone();
+            let events = self.engine.events();
+            let control_data = Arc::clone(&self.server_control_data);
+            move || supervisor::supervise("data receiver", services, notify, data,
+                || ports::bind_tcp(ports.data),
+                |listener| server::data_connection(listener, events.clone(), control_data.clone()))
+        });
+    }
+}
+
+// Saved to the identity file too, older versions only read that one
+fn set_display_name(identity: &mut Identity, name: &str) {
+    let name = identity::sanitize_display_name(name);
+    if name.is_empty() || name == identity.display_name {
+        return;
+    }
+    identity.display_name = name;
+    if let Err(e) = identity.save() {
+        eprintln!("Cannot save the display name: {}", e);
+    }
+}
+
+// Edited from the Receive tab, saved in the config so a daemon the window is attached to gets them too
+fn apply_receiver_config(config: &Config, settings: &mut ReceiverSettings) {
+    settings.ignore_untrusted_permissions = config.ignore_untrusted_permissions;
+    settings.trusted_peers = config.trusted_peers.iter().cloned().collect();
+    settings.symlink_policy = config.symlink_policy;
+}
+
+// The ports and the chunk size are read when needed, see common::config
+fn config_changed(previous: &Config, config: &Config, identity: &Mutex<Identity>, mdns: Option<&mdns::Mdns>, settings: &Mutex<ReceiverSettings>) {
+    if let Some(name) = &config.display_name
+        && config.display_name != previous.display_name
+    {
+        let mut identity = identity.lock().unwrap();
+        set_display_name(&mut identity, name);
+        let visible = settings.lock().unwrap().visibility == server::Visibility::Visible;
+        if let Some(mdns) = mdns.filter(|_| visible)
+            && let Err(e) = mdns.update(&identity)
+        {
+            eprintln!("Cannot update the mDNS announcement: {}", e);
+        }
+    }
+
+    if config.visibility != previous.visibility {
+        settings.lock().unwrap().visibility = config.visibility;
+        if let Some(mdns) = mdns {
+            let result = if config.visibility == server::Visibility::Visible {
+                mdns.update(&identity.lock().unwrap())
+            } else {
+                mdns.withdraw()
+            };
+            if let Err(e) = result {
+                eprintln!("Cannot update the mDNS announcement: {}", e);
+            }
+        }
+    }
+
+    apply_receiver_config(config, &mut settings.lock().unwrap());
+
+    // Transfers already running keep the directory they started in
+    if config.receive_dir != previous.receive_dir && !config::receive_dir_pinned() {
+        settings.lock().unwrap().receive_dir = config::receive_dir(config);
+    }
+}
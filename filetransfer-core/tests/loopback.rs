@@ -0,0 +1,78 @@
+// Two nodes in one process, one sending to the other over 127.0.0.1
+use std::fs;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use filetransfer_core::Node;
+use filetransfer_core::common::{config, notify, paths};
+use filetransfer_core::common::transfer_state::TransferType;
+use filetransfer_core::engine::{self, events::Event};
+use filetransfer_core::networking::client::PingResponse;
+use filetransfer_core::networking::ports::Ports;
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn start_node() -> Arc<Node> {
+    let (presence_events, _) = mpsc::channel();
+    Arc::new(Node::start(Ports::ephemeral(), notify::none(), presence_events))
+}
+
+#[test]
+fn sends_files_between_two_nodes() {
+    let dir = std::env::temp_dir().join(format!("ft-loopback-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (src, recv) = (dir.join("src"), dir.join("recv"));
+    fs::create_dir_all(&src).unwrap();
+    fs::create_dir_all(&recv).unwrap();
+    // Both nodes share the config dir and the identity, the peer is given by address
+    paths::set_config_dir(dir.join("config"));
+    config::pin_receive_dir(recv.clone());
+
+    let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(src.join("a.bin"), &content).unwrap();
+    // Same content, copied locally by the receiver instead of being sent again
+    fs::write(src.join("c.bin"), &content).unwrap();
+    fs::write(src.join("b.txt"), b"hello").unwrap();
+
+    let receiver = start_node();
+    let sender = start_node();
+    assert_ne!(receiver.ports.control, sender.ports.control);
+
+    // Accepts everything, answers once the three files ended
+    let (done, finished) = mpsc::channel();
+    thread::spawn({
+        let receiver = receiver.clone();
+        let events = receiver.subscribe_events();
+        move || {
+            let mut state = engine::State::default();
+            for event in events {
+                state.apply(&event);
+                if let Event::RequestReceived { id, request } = &event {
+                    receiver.accept_request(*id, request.files.iter().map(|f| f.name.clone()).collect());
+                }
+                let ended = state.transfers.values().filter(|t| t.ttype.is_finished()).cloned().collect::<Vec<_>>();
+                if ended.len() == 3 {
+                    let _ = done.send(ended);
+                    return;
+                }
+            }
+        }
+    });
+
+    let identity = receiver.identity.lock().unwrap().clone();
+    let files = ["a.bin", "b.txt", "c.bin"].iter().map(|name| src.join(name).to_string_lossy().into_owned()).collect();
+    let started = Instant::now();
+    sender.send_files(PingResponse::loopback(&identity, &receiver.ports), files);
+
+    let ended = finished.recv_timeout(TIMEOUT).expect("the transfers didn't end in time");
+    assert!(started.elapsed() < TIMEOUT);
+    for transfer in ended.iter() {
+        assert!(matches!(transfer.ttype, TransferType::CompletelyReceived), "{}: {:?}", transfer.dest_filepath, transfer.error_text());
+    }
+    assert_eq!(fs::read(recv.join("a.bin")).unwrap(), content);
+    assert_eq!(fs::read(recv.join("c.bin")).unwrap(), content);
+    assert_eq!(fs::read(recv.join("b.txt")).unwrap(), b"hello");
+
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -1,21 +0,0 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
-use sha2::{Sha256, Digest};
-
-pub fn hash_file_sha256(path: &str) -> std::io::Result<[u8; 32]> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
-
-    loop {
-        let n = reader.read(&mut buffer)?;
-        if n == 0 { break; }
-        hasher.update(&buffer[..n]);
-    }
-
-    let result = hasher.finalize();
-    let mut hash_bytes = [0u8; 32];
-    hash_bytes.copy_from_slice(&result[..]);
-    Ok(hash_bytes)
-}
\ No newline at end of file
@@ -1,3 +0,0 @@
-pub mod hash;
-pub mod transfer_state;
-pub mod counter;
\ No newline at end of file
@@ -1,11 +1,25 @@
 use eframe::{egui};
-use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::collections::{HashSet, VecDeque};
+use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 
-use crate::common;
-use crate::networking::client::PingResponse;
-use crate::networking::{client, server};
+use filetransfer_core::Node;
+use filetransfer_core::common;
+use filetransfer_core::common::config::{self, Config};
+use filetransfer_core::common::history::{self, Direction, HistoryEntry, HistoryReader};
+use filetransfer_core::common::notify::Notify;
+use filetransfer_core::common::paths;
+use filetransfer_core::daemon::client::{self as daemon_client, DaemonClient};
+use filetransfer_core::engine;
+use filetransfer_core::engine::events::Event;
+use filetransfer_core::networking::client::PingResponse;
+use filetransfer_core::networking::server;
+use filetransfer_core::networking::contacts;
+use filetransfer_core::networking::ports::Ports;
+use filetransfer_core::networking::supervisor::ServiceState;
+use filetransfer_core::networking::presence::PresenceEvent;
+
+const MAX_PEER_ACTIVITY: usize = 5;
 
 extern crate nfd;
 
@@ -26,65 +40,226 @@ impl RequestDetails {
     }
 }
 
+// The Settings tab, as typed. Checked and written to config.toml on save
+#[derive(Default)]
+struct SettingsForm {
+    display_name: String,
+    receive_dir: String,
+    chunk_size: String,
+    discovery_interval: String,
+    control_port: String,
+    data_port: String,
+    info_port: String,
+    discovery_port: String,
+    // What the last save did
+    result: Option<Result<String, String>>,
+}
+
+impl SettingsForm {
+    fn new(config: &Config, display_name: &str) -> Self {
+        SettingsForm {
+            display_name: config.display_name.clone().unwrap_or_else(|| display_name.to_string()),
+            receive_dir: config.receive_dir.as_ref().map(|dir| dir.display().to_string()).unwrap_or_default(),
+            chunk_size: config.chunk_size.to_string(),
+            discovery_interval: config.discovery_interval_secs.to_string(),
+            control_port: config.ports.control.to_string(),
+            data_port: config.ports.data.to_string(),
+            info_port: config.ports.info.to_string(),
+            discovery_port: config.ports.discovery.to_string(),
+            result: None,
+        }
+    }
+
+    fn to_config(&self) -> Result<Config, String> {
+        fn number<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+            value.trim().parse().map_err(|_| format!("Invalid {}: {}", what, value))
+        }
+
+        let display_name = self.display_name.trim();
+        let receive_dir = self.receive_dir.trim();
+        let config = Config {
+            display_name: (!display_name.is_empty()).then(|| display_name.to_string()),
+            receive_dir: (!receive_dir.is_empty()).then(|| receive_dir.into()),
+            chunk_size: number(&self.chunk_size, "chunk size")?,
+            discovery_interval_secs: number(&self.discovery_interval, "discovery interval")?,
+            ports: Ports {
+                control: number(&self.control_port, "control port")?,
+                data: number(&self.data_port, "data port")?,
+                info: number(&self.info_port, "info port")?,
+                discovery: number(&self.discovery_port, "discovery port")?,
+            },
+            // The visibility and the receiver settings are set in the Receive tab
+            ..config::current()
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
 pub struct MyApp {
-    gui_state: usize, //0 = send, 1 = receive, 2 = status
+    gui_state: usize, //0 = send, 1 = receive, 2 = status, 3 = settings, 4 = history
     selected_step: usize,
     selected_dest: PingResponse,
     selected_files: Vec<String>,
-    responders: Arc<Mutex<HashSet<client::PingResponse>>>,
-    transfer_status: Arc<Mutex<HashMap<u32, common::transfer_state::TransferState>>>,
-    server_control_data: Arc<Mutex<server::ServerControlData>>,
-    incoming_requests: Arc<Mutex<HashMap<u32, server::RequestData>>>,
+    // Shared with the threads looking for a peer to resend to
+    node: Arc<Node>,
+    // A running `ft daemon` receives and sends, the node only finds the peers
+    daemon: Option<DaemonClient>,
+    presence_events: mpsc::Receiver<PresenceEvent>,
+    peer_activity: VecDeque<String>,
+    favorite_input: String,
+    contact_code_input: String,
+    contact_name_input: String,
+    contact_error: Option<String>,
+    engine_events: mpsc::Receiver<Event>,
+    // Requests and transfers as seen through the engine events
+    transfers: engine::State,
     show_details_popup: Option<RequestDetails>,
     show_details_popup_open: bool,
-    confirmed_requests: HashSet<u32>, 
     context: Arc<Mutex<Option<egui::Context>>>,
+    settings_form: SettingsForm,
+    // What was appended to the file is read when a transfer ends, see common::history
+    history: Vec<HistoryEntry>,
+    history_reader: HistoryReader,
+    history_stale: bool,
+    history_search: String,
+    history_direction: Option<Direction>,
+    // Some(true) for the completed transfers only, Some(false) for the failed ones
+    history_completed: Option<bool>,
+    history_error: Option<String>,
+    // Finding the peer may probe its address, it's done by a thread that answers here
+    resend_sender: mpsc::Sender<(HistoryEntry, Option<PingResponse>)>,
+    resends: mpsc::Receiver<(HistoryEntry, Option<PingResponse>)>,
+    resend_pending: Option<String>,
 }
 
 impl MyApp {
-    pub fn new() -> Self {
-        let app = Self {
+    pub fn new(ports: Ports) -> Self {
+        let (presence_sender, presence_events) = mpsc::channel();
+        let context = Arc::new(Mutex::new(None::<egui::Context>));
+
+        let notify: Notify = {
+            let context = context.clone();
+            Arc::new(move || {
+                if let Some(context) = context.lock().unwrap().as_ref() {
+                    context.request_repaint();
+                }
+            })
+        };
+
+        let (daemon, daemon_events) = attach_daemon().unzip();
+        let (resend_sender, resends) = mpsc::channel();
+        let node = Arc::new(match &daemon {
+            Some(_) => Node::start_discovery(ports.info, notify.clone(), presence_sender),
+            None => Node::start(ports, notify.clone(), presence_sender),
+        });
+
+        // Forwarded so the window is repainted as soon as something happens
+        let (engine_sender, engine_events) = mpsc::channel();
+        thread::spawn({
+            let subscription = daemon_events.unwrap_or_else(|| node.subscribe_events());
+            move || {
+                for event in subscription {
+                    if engine_sender.send(event).is_err() {
+                        break;
+                    }
+                    notify();
+                }
+            }
+        });
+
+        Self {
             gui_state: 0,
             selected_step: 0,
             selected_dest: PingResponse::default(),
             selected_files: Vec::new(),
-            responders: Arc::new(std::sync::Mutex::new(HashSet::new())),
-            transfer_status: Arc::new(std::sync::Mutex::new(HashMap::new())),
-            server_control_data: Arc::new(std::sync::Mutex::new(server::ServerControlData::default())),
-            incoming_requests: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            presence_events,
+            peer_activity: VecDeque::new(),
+            favorite_input: String::new(),
+            contact_code_input: String::new(),
+            contact_name_input: String::new(),
+            contact_error: None,
+            engine_events,
+            transfers: engine::State::default(),
             show_details_popup: None,
             show_details_popup_open: false,
-            confirmed_requests: HashSet::new(),
-            context: Arc::new(std::sync::Mutex::new(None)),
-        };
+            context,
+            settings_form: SettingsForm::default(),
+            history: Vec::new(),
+            history_reader: HistoryReader::default(),
+            history_stale: true,
+            history_search: String::new(),
+            history_direction: None,
+            history_completed: None,
+            history_error: None,
+            resend_sender,
+            resends,
+            resend_pending: None,
+            node,
+            daemon,
+        }
+    }
 
-        app.start_threads();
+}
 
-        app
+fn send_files(node: &Node, daemon: Option<&DaemonClient>, peer: PingResponse, files: Vec<String>) {
+    let Some(daemon) = daemon else {
+        node.send_files(peer, files);
+        return;
+    };
+    // "This device" is sent by device ID too, the daemon resolves its own to its loopback address
+    if let Err(e) = daemon.send_files(&peer.peer_id(), files) {
+        eprintln!("The daemon cannot send the files: {}", e);
     }
+}
 
-    fn start_threads(&self) {
-        let mut responders = self.responders.clone();
-        let mut context = self.context.clone();
-        thread::spawn(move || client::info_socket(&mut responders, &context));
-    
-        thread::spawn(|| server::info_socket());
+fn accept_request(node: &Node, daemon: Option<&DaemonClient>, id: u32, accepted_files: Vec<String>) {
+    let Some(daemon) = daemon else {
+        node.accept_request(id, accepted_files);
+        return;
+    };
+    if let Err(e) = daemon.accept_request(id, Some(accepted_files)) {
+        eprintln!("The daemon cannot answer request {}: {}", id, e);
+    }
+}
 
-        thread::spawn({
-            let status = self.transfer_status.clone();
-            let server_control_data = self.server_control_data.clone();
-            let incoming_requests = self.incoming_requests.clone();
-            let responders = self.responders.clone();
-            move || server::control_connection(status, server_control_data, responders, incoming_requests)
-        });
+// In the file manager of the platform
+fn open_folder(path: &str) {
+    let folder = std::path::Path::new(path).parent().unwrap_or(std::path::Path::new("."));
+    #[cfg(target_os = "windows")]
+    let opener = "explorer";
+    #[cfg(target_os = "macos")]
+    let opener = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let opener = "xdg-open";
+    if let Err(e) = std::process::Command::new(opener).arg(folder).spawn() {
+        eprintln!("Cannot open {}: {}", folder.display(), e);
+    }
+}
 
-        std::thread::spawn({
-            let status = Arc::clone(&self.transfer_status);
-            let control_data = Arc::clone(&self.server_control_data);
-            move || {
-                server::data_connection(status, control_data);
-            }
-        });
+// Receive tab settings, saved for the next start and for a daemon this window is attached to.
+// The config watcher of every running node applies them, see Node::start
+fn save_config(what: &str, edit: impl FnOnce(&mut Config)) {
+    let mut config = config::current();
+    edit(&mut config);
+    if let Err(e) = config::save(&config) {
+        eprintln!("Cannot save the {}: {}", what, e);
+    }
+}
+
+// The calls and the events go over two connections, see filetransfer_core::daemon
+fn attach_daemon() -> Option<(DaemonClient, mpsc::Receiver<Event>)> {
+    let path = paths::daemon_socket();
+    let client = DaemonClient::connect(&path).ok()?;
+    match daemon_client::subscribe(&path) {
+        Ok(events) => {
+            eprintln!("Attached to the daemon on {}", path.display());
+            Some((client, events))
+        }
+        Err(e) => {
+            eprintln!("Cannot follow the daemon on {}: {}", path.display(), e);
+            None
+        }
     }
 }
 
@@ -98,12 +273,62 @@ impl eframe::App for MyApp {
 
         drop(ctx_lock);
 
+        while let Ok(event) = self.engine_events.try_recv() {
+            self.history_stale |= matches!(&event, Event::TransferAdded { .. } | Event::TransferChanged { .. });
+            self.transfers.apply(&event);
+        }
+        self.transfers.prune_finished(engine::MAX_FINISHED_TRANSFERS);
+
+        while let Ok((entry, peer)) = self.resends.try_recv() {
+            self.resend_pending = None;
+            match peer {
+                Some(peer) => {
+                    send_files(&self.node, self.daemon.as_ref(), peer, vec![entry.path.clone()]);
+                    self.gui_state = 2;
+                }
+                None => self.history_error = Some(format!("{} is not online", entry.peer())),
+            }
+        }
+
+        while let Ok(event) = self.presence_events.try_recv() {
+            let message = match event {
+                PresenceEvent::Joined(peer) => format!("{} ({}) joined", peer.name(), peer.addr),
+                PresenceEvent::Left(peer) => format!("{} ({}) left", peer.name(), peer.addr),
+            };
+            self.peer_activity.push_front(message);
+            self.peer_activity.truncate(MAX_PEER_ACTIVITY);
+        }
+
+        egui::TopBottomPanel::bottom("services").show(ctx, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                if self.daemon.is_some() {
+                    ui.label(format!("Attached to the daemon on {}", paths::daemon_socket().display()));
+                    ui.separator();
+                }
+                for (name, state) in self.node.services.lock().unwrap().iter() {
+                    match state {
+                        ServiceState::Offline(reason) => {
+                            ui.colored_label(egui::Color32::RED, format!("{} offline: {}", name, reason));
+                        }
+                        _ => {
+                            ui.label(format!("{}: {}", name, state));
+                        }
+                    }
+                    ui.separator();
+                }
+            });
+        });
+
         egui::TopBottomPanel::top("navbar").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                for (i, label) in ["Send", "Receive", "Status"].iter().enumerate() {
+                for (i, label) in ["Send", "Receive", "Status", "Settings", "History"].iter().enumerate() {
                     if ui.selectable_label(self.gui_state == i, *label).clicked() {
                         self.gui_state = i;
                         self.selected_step = 0;
+                        // The file may have been edited by hand meanwhile
+                        if i == 3 {
+                            self.settings_form = SettingsForm::new(&config::current(), &self.node.identity.lock().unwrap().display_name);
+                        }
                     }
                 }
             });
@@ -138,25 +363,105 @@ impl eframe::App for MyApp {
                 0 => {
                     match self.selected_step {
                         0 => {
-                            let responders = self.responders.lock().unwrap();
+                            ui.horizontal(|ui| {
+                                ui.label("Add a peer by address or hostname:");
+                                ui.text_edit_singleline(&mut self.favorite_input);
+                                if ui.button("Add").clicked() {
+                                    let address = self.favorite_input.trim().to_string();
+                                    self.node.add_favorite(&address);
+                                    self.favorite_input.clear();
+                                }
+                            });
+
+                            let mut to_remove = None;
+                            let favorites = self.node.favorites.lock().unwrap();
+                            if !favorites.addresses.is_empty() {
+                                ui.add(egui::Label::new("Favorites").wrap(true));
+                            }
+                            for address in favorites.addresses.iter() {
+                                ui.horizontal(|ui| {
+                                    match favorites.online.get(address) {
+                                        Some(peer) => {
+                                            ui.add(egui::Label::new(format!("{}: online ({}, {})", address, peer.name(), peer.addr)).wrap(true));
+                                            if ui.button("Select").clicked() {
+                                                self.selected_dest = peer.clone();
+                                                self.selected_step = 1;
+                                            }
+                                        }
+                                        None => {
+                                            ui.add(egui::Label::new(format!("{}: unreachable", address)).wrap(true));
+                                        }
+                                    }
+                                    if ui.button("❌").on_hover_text("Remove").clicked() {
+                                        to_remove = Some(address.clone());
+                                    }
+                                });
+                            }
+                            drop(favorites);
+                            if let Some(address) = to_remove {
+                                let mut favorites = self.node.favorites.lock().unwrap();
+                                favorites.remove(&address);
+                                if let Err(e) = favorites.save() {
+                                    eprintln!("Cannot save the favorites: {}", e);
+                                }
+                            }
+                            ui.separator();
+
+                            // Cloned, the discovery thread locks the presence table before the responders
+                            let responders = self.node.responders.lock().unwrap().clone();
                             for responder in responders.iter() {
+                                let status = self.node.presence.lock().unwrap().status(responder).cloned();
                                 ui.horizontal(|ui| {
                                     ui.vertical(|ui| {
+                                        ui.add(egui::Label::new(egui::RichText::new(responder.name()).strong()).wrap(true));
                                         ui.add(egui::Label::new(format!("{}", responder.addr)).wrap(true));
                                         ui.add(egui::Label::new(format!("OS: {}", responder.os)).wrap(true));
                                         ui.add(egui::Label::new(format!("Hostname: {}", responder.hostname)).wrap(true));
+                                        if !responder.version.is_empty() {
+                                            ui.add(egui::Label::new(format!("Version: {}", responder.version)).wrap(true));
+                                        }
+                                        if !responder.capabilities.is_empty() {
+                                            ui.add(egui::Label::new(format!("Features: {}", responder.capabilities.join(", "))).wrap(true));
+                                        }
+                                        if !responder.interface.is_empty() {
+                                            ui.add(egui::Label::new(format!("Via: {}", responder.interface)).wrap(true));
+                                        }
+                                        if let Some(status) = &status {
+                                            let rtt = status.rtt.map(|rtt| format!(", RTT: {} ms", rtt.as_millis())).unwrap_or_default();
+                                            ui.add(egui::Label::new(format!("Last seen: {} s ago{}", status.last_seen.elapsed().as_secs(), rtt)).wrap(true));
+                                        }
                                     });
                                     ui.add_space(16.0);
                                     if ui.button("Select").clicked() {
                                         self.selected_dest = responder.clone();
                                         self.selected_step = 1;
                                     }
+                                    if ui.button("☆").on_hover_text("Add to favorites").clicked() {
+                                        self.node.add_favorite(&responder.addr.ip().to_string());
+                                    }
                                 });
                                 ui.separator();
                             }
+
+                            ui.horizontal(|ui| {
+                                ui.add(egui::Label::new("This device (loopback), for testing").wrap(true));
+                                ui.add_space(16.0);
+                                if ui.button("Select").clicked() {
+                                    self.selected_dest = PingResponse::loopback(&self.node.identity.lock().unwrap(), &self.node.ports);
+                                    self.selected_step = 1;
+                                }
+                            });
+
+                            if !self.peer_activity.is_empty() {
+                                ui.add_space(8.0);
+                                ui.add(egui::Label::new("Recent activity").wrap(true));
+                                for message in self.peer_activity.iter() {
+                                    ui.add(egui::Label::new(message).wrap(true));
+                                }
+                            }
                         },
                         1 => {
-                            ui.add(egui::Label::new(format!("Current dest: {} OS: {} Hostname: {}", self.selected_dest.addr,self.selected_dest.os,self.selected_dest.hostname)).wrap(true));
+                            ui.add(egui::Label::new(format!("Current dest: {} ({}) OS: {} Hostname: {}", self.selected_dest.name(), self.selected_dest.addr, self.selected_dest.os, self.selected_dest.hostname)).wrap(true));
                             
                             if ui.button("Select files").clicked() {
                                 let result = nfd::dialog_multiple().open().unwrap_or_else(|e| {
@@ -193,13 +498,7 @@ impl eframe::App for MyApp {
                             
                                 ui.add_space(8.0);
                                 if ui.button("Send").clicked() {
-                                    let dest = self.selected_dest.addr.clone();
-                                    let files = self.selected_files.clone();
-                                    let status = self.transfer_status.clone();
-
-                                    thread::spawn(move || {
-                                        client::control_connection(dest, files, status);
-                                    });
+                                    send_files(&self.node, self.daemon.as_ref(), self.selected_dest.clone(), self.selected_files.clone());
 
                                     self.selected_step = 2;
                                 }
@@ -208,92 +507,202 @@ impl eframe::App for MyApp {
                         _ => {}
                     }
                 },
-                1 => {
-                    match self.selected_step {
-                        0 => {
-                            ui.add(egui::Label::new("Incoming requests").wrap(true));
+                1 if self.selected_step == 0 => {
+                    ui.add(egui::Label::new("Incoming requests").wrap(true));
 
-                            let incoming_requests = self.incoming_requests.lock().unwrap();
+                    let mut settings = self.node.receiver_settings.lock().unwrap();
+                    if ui.checkbox(&mut settings.ignore_untrusted_permissions, "Ignore file permissions from untrusted peers").clicked() {
+                        let ignore = settings.ignore_untrusted_permissions;
+                        save_config("permission setting", |config| config.ignore_untrusted_permissions = ignore);
+                    }
+                    let mut accept_symlinks = settings.symlink_policy == server::SymlinkPolicy::InsideDestination;
+                    if ui.checkbox(&mut accept_symlinks, "Accept symlinks pointing inside the receive folder").clicked() {
+                        settings.symlink_policy = if accept_symlinks {
+                            server::SymlinkPolicy::InsideDestination
+                        } else {
+                            server::SymlinkPolicy::Refuse
+                        };
+                        let policy = settings.symlink_policy;
+                        save_config("symlink policy", |config| config.symlink_policy = policy);
+                    }
 
-                            for (key, request) in incoming_requests.iter() {
-                                if self.confirmed_requests.contains(key) {
-                                    continue;
-                                }
+                    let previous_visibility = settings.visibility;
+                    ui.horizontal(|ui| {
+                        ui.label("Visibility:");
+                        ui.radio_value(&mut settings.visibility, server::Visibility::Visible, "Visible to everyone");
+                        ui.radio_value(&mut settings.visibility, server::Visibility::ContactsOnly, "Contacts only");
+                        ui.radio_value(&mut settings.visibility, server::Visibility::Hidden, "Hidden (favorites only)");
+                    });
+                    let visibility = settings.visibility;
+                    drop(settings);
 
-                                ui.horizontal(|ui| {
-                                    ui.add(egui::Label::new(format!("ID: {} From: {}", key, request.from.addr)).wrap(true));
-                                    if ui.button("Show details").clicked() {
-                                        self.show_details_popup = Some(RequestDetails::new(*key));
-                                        self.show_details_popup_open = true;
+                    // The config watcher also updates the mDNS announcement (see Node::start)
+                    if visibility != previous_visibility {
+                        save_config("visibility", |config| config.visibility = visibility);
+                    }
+
+                    let pairing_code = contacts::pairing_code(&self.node.identity.lock().unwrap());
+                    ui.horizontal(|ui| {
+                        ui.add(egui::Label::new(format!("Pairing code: {}", pairing_code)).wrap(true));
+                        if ui.button("Copy").clicked() {
+                            ui.output_mut(|output| output.copied_text = pairing_code.clone());
+                        }
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Add contact, code:");
+                        ui.text_edit_singleline(&mut self.contact_code_input);
+                        ui.label("name:");
+                        ui.text_edit_singleline(&mut self.contact_name_input);
+                        if ui.button("Add").clicked() {
+                            let mut contacts = self.node.contacts.lock().unwrap();
+                            match contacts.add(&self.contact_code_input, &self.contact_name_input) {
+                                Ok(()) => {
+                                    if let Err(e) = contacts.save() {
+                                        eprintln!("Cannot save the contacts: {}", e);
                                     }
-                                });
+                                    self.contact_code_input.clear();
+                                    self.contact_name_input.clear();
+                                    self.contact_error = None;
+                                }
+                                Err(e) => self.contact_error = Some(e.to_string()),
                             }
+                        }
+                    });
+                    if let Some(error) = &self.contact_error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
 
-                            drop(incoming_requests);
-
-                            if self.show_details_popup.is_some() {
-                                egui::Window::new("Request details")
-                                    .open(&mut self.show_details_popup_open)
-                                    .min_width(400.0)
-                                    .max_width(400.0)
-                                    .min_height(300.0)
-                                    .max_height(300.0)
-                                    .show(ctx, |ui| {
-                                        if let Some(request_details) = &mut self.show_details_popup {
-                                            if let Some(request) = self.incoming_requests.lock().unwrap().get_mut(&request_details.key) {
-                                                ui.add(egui::Label::new(format!("ID: {}", &request_details.key)).wrap(true));
-                                                ui.add(egui::Label::new(format!("From: {}", request.from.addr)).wrap(true));
-                                                ui.separator();
+                    let mut contact_to_remove = None;
+                    for contact in self.node.contacts.lock().unwrap().list.iter() {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Label::new(format!("Contact: {} ({})", contact.name, contact.device_id)).wrap(true));
+                            if ui.button("❌").on_hover_text("Remove").clicked() {
+                                contact_to_remove = Some(contact.device_id.clone());
+                            }
+                        });
+                    }
+                    if let Some(device_id) = contact_to_remove {
+                        let mut contacts = self.node.contacts.lock().unwrap();
+                        contacts.remove(&device_id);
+                        if let Err(e) = contacts.save() {
+                            eprintln!("Cannot save the contacts: {}", e);
+                        }
+                    }
+                    ui.separator();
+
+                    for (key, request) in self.transfers.requests.iter() {
+                        ui.horizontal(|ui| {
+                            ui.add(egui::Label::new(format!("ID: {} From: {}", key, request.from.addr)).wrap(true));
+                            if ui.button("Show details").clicked() {
+                                self.show_details_popup = Some(RequestDetails::new(*key));
+                                self.show_details_popup_open = true;
+                            }
+                        });
+                    }
 
-                                                egui::ScrollArea::vertical()
-                                                    .max_height(180.0)
-                                                    .show(ui, |ui| {
-                                                        for (name, size) in request.files.iter() {
-                                                            ui.horizontal(|ui| {
-                                                                ui.add(egui::Label::new(format!("{} (Size: {})", name, size)).wrap(true));
-                                                                let mut checked = request_details.accepted_files.contains(name);
-                                                                if ui.checkbox(&mut checked, "Accept").clicked() {
-                                                                    if checked {
-                                                                        request_details.accepted_files.insert(name.clone());
-                                                                    } else {
-                                                                        request_details.accepted_files.remove(name);
-                                                                    }
-                                                                }
-                                                            });
-                                                            ui.separator();
+                    if self.show_details_popup.is_some() {
+                        egui::Window::new("Request details")
+                            .open(&mut self.show_details_popup_open)
+                            .min_width(400.0)
+                            .max_width(400.0)
+                            .min_height(300.0)
+                            .max_height(300.0)
+                            .show(ctx, |ui| {
+                                if let Some(request_details) = &mut self.show_details_popup
+                                    && let Some(request) = self.transfers.requests.get(&request_details.key)
+                                {
+                                    ui.add(egui::Label::new(format!("ID: {}", &request_details.key)).wrap(true));
+                                    ui.add(egui::Label::new(format!("From: {}", request.from.addr)).wrap(true));
+
+                                    let peer_ip = request.from.addr.ip().to_string();
+                                    let mut settings = self.node.receiver_settings.lock().unwrap();
+                                    let mut trusted = settings.trusted_peers.contains(&peer_ip);
+                                    if ui.checkbox(&mut trusted, "Trust this peer (keep file permissions)").clicked() {
+                                        if trusted {
+                                            settings.trusted_peers.insert(peer_ip.clone());
+                                        } else {
+                                            settings.trusted_peers.remove(&peer_ip);
+                                        }
+                                        save_config("trusted peers", |config| {
+                                            config.trusted_peers.retain(|known| *known != peer_ip);
+                                            if trusted {
+                                                config.trusted_peers.push(peer_ip);
+                                            }
+                                        });
+                                    }
+                                    drop(settings);
+                                    ui.separator();
+
+                                    egui::ScrollArea::vertical()
+                                        .max_height(180.0)
+                                        .show(ui, |ui| {
+                                            for common::manifest::FileEntry { name, size, kind, .. } in request.files.iter() {
+                                                ui.horizontal(|ui| {
+                                                    match kind {
+                                                        common::manifest::EntryKind::File => {
+                                                            ui.add(egui::Label::new(format!("{} (Size: {})", name, size)).wrap(true));
+                                                        },
+                                                        common::manifest::EntryKind::Symlink(target) => {
+                                                            ui.add(egui::Label::new(format!("{} (Symlink to {})", name, target)).wrap(true));
                                                         }
-                                                    });
-
-                                                ui.add_space(8.0);
-                                                if ui.button("Send response").clicked() {
-                                                    let files = request_details.accepted_files.iter().cloned().collect::<Vec<_>>();
-                                                    request.accepted_files = Some(files);
-                                                    self.confirmed_requests.insert(request_details.key);
-                                                    self.show_details_popup = None;
-                                                }
+                                                    }
+                                                    let mut checked = request_details.accepted_files.contains(name);
+                                                    if ui.checkbox(&mut checked, "Accept").clicked() {
+                                                        if checked {
+                                                            request_details.accepted_files.insert(name.clone());
+                                                        } else {
+                                                            request_details.accepted_files.remove(name);
+                                                        }
+                                                    }
+                                                });
+                                                ui.separator();
                                             }
-                                        }
-                                    });
+                                        });
 
-                                if self.show_details_popup.is_none() {
-                                    self.show_details_popup_open = false;
+                                    ui.add_space(8.0);
+                                    if ui.button("Send response").clicked() {
+                                        accept_request(&self.node, self.daemon.as_ref(), request_details.key, request_details.accepted_files.iter().cloned().collect());
+                                        self.show_details_popup = None;
+                                    }
                                 }
-                            }
-                        },
-                        _ => {}
+                            });
+
+                        if self.show_details_popup.is_none() {
+                            self.show_details_popup_open = false;
+                        }
                     }
                 }, 
                 2 => {
-                    //println!("Acquiring lock for transfer status - app.rs line 170");
-                    let transfer_status = self.transfer_status.lock().unwrap();
-                    //println!("Lock acquired for transfer status - app.rs line 172");
+                    let transfer_status = &self.transfers.transfers;
                     let mut status_vec: Vec<_> = transfer_status.iter().collect();
                     status_vec.sort_by_key(|(k, _)| *k);
 
+                    // Rates and ETAs change without any event, refresh while something is running
+                    if status_vec.iter().any(|(_, state)| !state.ttype.is_finished()) {
+                        ctx.request_repaint_after(std::time::Duration::from_millis(500));
+                    }
+
+                    let batches = common::transfer_state::batches(transfer_status.values());
+                    for (batch, progress) in batches.iter().filter(|(_, progress)| progress.finished < progress.transfers) {
+                        let eta = progress.eta().map(common::rate::format_duration).unwrap_or_else(|| String::from("unknown"));
+                        ui.label(format!("Request {}: {}/{} files done, {} / {}, {}, ETA {}",
+                            batch,
+                            progress.finished,
+                            progress.transfers,
+                            common::transfer_state::format_bytes(progress.bytes_done),
+                            common::transfer_state::format_bytes(progress.bytes_total),
+                            common::rate::format_rate(progress.bytes_per_sec),
+                            eta));
+                    }
+                    if !batches.is_empty() {
+                        ui.separator();
+                    }
+
                     egui::ScrollArea::vertical().show(ui, |ui| {
                         for (id, state) in status_vec {
                             ui.horizontal(|ui| {
-                                match state.ttype {
+                                match &state.ttype {
                                     common::transfer_state::TransferType::Sending => {
                                         ui.add(egui::Label::new(format!("ID: {} Status: Sending Filepath: {}, Percentage: {}", id, state.original_filepath, state.percentage)).wrap(true));
                                     },
@@ -312,17 +721,192 @@ impl eframe::App for MyApp {
                                     common::transfer_state::TransferType::CompletelyReceived => {
                                         ui.add(egui::Label::new(format!("ID: {} Status: Completed Type: Receive Filepath: {}, Percentage: {}", id, state.original_filepath, state.percentage)).wrap(true));
                                     },
-                                    common::transfer_state::TransferType::Error => {
-                                        ui.add(egui::Label::new(format!("ID: {} Status: Error Filepath: {}, Percentage: {}", id, state.dest_filepath, state.percentage)).wrap(true));
+                                    common::transfer_state::TransferType::Error(_) => {
+                                        // Sending errors only know the original path
+                                        let filepath = if state.dest_filepath.is_empty() { &state.original_filepath } else { &state.dest_filepath };
+                                        ui.add(egui::Label::new(format!("ID: {} Status: Error: {} Filepath: {}, Percentage: {}", id, state.error_text().unwrap_or_default(), filepath, state.percentage)).wrap(true));
                                     }
                                 }
                             });
+                            let speed = match (state.bytes_per_sec(), state.eta()) {
+                                (Some(rate), Some(eta)) => format!(", {}, ETA {}", common::rate::format_rate(rate), common::rate::format_duration(eta)),
+                                _ => String::new(),
+                            };
+                            ui.label(format!("{} / {} in {:.1} s{}, peer {}",
+                                common::transfer_state::format_bytes(state.bytes_done),
+                                common::transfer_state::format_bytes(state.bytes_total),
+                                state.duration().as_secs_f32(),
+                                speed,
+                                state.peer));
+                            egui::CollapsingHeader::new("Events").id_source(("transfer events", *id)).show(ui, |ui| {
+                                for event in state.events.iter() {
+                                    let offset = event.at.duration_since(state.started_at).unwrap_or_default();
+                                    ui.label(format!("+{:.1} s  {}", offset.as_secs_f32(), event.message));
+                                }
+                            });
                             ui.separator();
                         }
                     });
 
                     //println!("Releasing lock for transfer status - app.rs line 188");
                 },
+                3 => {
+                    let form = &mut self.settings_form;
+                    ui.add(egui::Label::new(format!("Saved in {}, edits to the file show up here too", config::config_file().display())).wrap(true));
+                    ui.separator();
+
+                    egui::Grid::new("settings").num_columns(2).show(ui, |ui| {
+                        ui.label("Display name:");
+                        ui.text_edit_singleline(&mut form.display_name);
+                        ui.end_row();
+
+                        ui.label("Receive folder:");
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut form.receive_dir);
+                            if ui.button("Choose").clicked() {
+                                match nfd::open_pick_folder(None) {
+                                    Ok(Response::Okay(dir)) => form.receive_dir = dir,
+                                    Ok(_) => (),
+                                    Err(e) => eprintln!("Cannot open the folder picker: {}", e),
+                                }
+                            }
+                        });
+                        ui.end_row();
+
+                        ui.label("Chunk size (bytes):");
+                        ui.text_edit_singleline(&mut form.chunk_size);
+                        ui.end_row();
+
+                        ui.label("Discovery interval (s):");
+                        ui.text_edit_singleline(&mut form.discovery_interval);
+                        ui.end_row();
+
+                        for (label, port) in [
+                            ("Control port:", &mut form.control_port),
+                            ("Data port:", &mut form.data_port),
+                            ("Info port:", &mut form.info_port),
+                            ("Discovery port:", &mut form.discovery_port),
+                        ] {
+                            ui.label(label);
+                            ui.text_edit_singleline(port);
+                            ui.end_row();
+                        }
+                    });
+                    ui.add(egui::Label::new("An empty receive folder means the one the app was started from, a port of 0 a free one. Ports apply after a restart.").wrap(true));
+
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("Save").clicked() {
+                            form.result = Some(form.to_config().and_then(|config| {
+                                let previous = config::current();
+                                config::save(&config)?;
+                                Ok(if config.ports != previous.ports {
+                                    String::from("Saved, restart to use the new ports")
+                                } else {
+                                    String::from("Saved")
+                                })
+                            }));
+                        }
+                        // Only filled in, saved with the button above
+                        if ui.button("Defaults").clicked() {
+                            *form = SettingsForm::new(&Config::default(), &self.node.identity.lock().unwrap().display_name);
+                        }
+                    });
+                    match &form.result {
+                        Some(Ok(message)) => {
+                            ui.label(message);
+                        }
+                        Some(Err(error)) => {
+                            ui.colored_label(egui::Color32::RED, error);
+                        }
+                        None => (),
+                    }
+                },
+                4 => {
+                    if self.history_stale {
+                        self.history.extend(self.history_reader.read_new());
+                        let extra = self.history.len().saturating_sub(history::MAX_ENTRIES);
+                        self.history.drain(..extra);
+                        self.history_stale = false;
+                    }
+
+                    ui.horizontal(|ui| {
+                        ui.label("Search:");
+                        ui.text_edit_singleline(&mut self.history_search);
+                        if ui.button("Refresh").clicked() {
+                            self.history_stale = true;
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.selectable_value(&mut self.history_direction, None, "All");
+                        ui.selectable_value(&mut self.history_direction, Some(Direction::Sent), "Sent");
+                        ui.selectable_value(&mut self.history_direction, Some(Direction::Received), "Received");
+                        ui.separator();
+                        ui.selectable_value(&mut self.history_completed, None, "Any outcome");
+                        ui.selectable_value(&mut self.history_completed, Some(true), "Completed");
+                        ui.selectable_value(&mut self.history_completed, Some(false), "Failed");
+                    });
+                    if let Some(peer) = &self.resend_pending {
+                        ui.label(format!("Looking for {}...", peer));
+                    }
+                    if let Some(error) = &self.history_error {
+                        ui.colored_label(egui::Color32::RED, error);
+                    }
+                    ui.separator();
+
+                    let search = self.history_search.trim().to_lowercase();
+                    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+                    let mut resend = None;
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        // Newest first
+                        for entry in self.history.iter().rev()
+                            .filter(|entry| self.history_direction.is_none_or(|direction| entry.direction == direction))
+                            .filter(|entry| self.history_completed.is_none_or(|completed| entry.error.is_none() == completed))
+                            .filter(|entry| search.is_empty() || [&entry.file, &entry.path, &entry.peer_name, &entry.peer_address].iter().any(|field| field.to_lowercase().contains(&search)))
+                        {
+                            let (verb, preposition) = match entry.direction {
+                                Direction::Sent => ("Sent", "to"),
+                                Direction::Received => ("Received", "from"),
+                            };
+                            ui.add(egui::Label::new(format!("{} ago: {} {} ({}) {} {} in {:.1} s",
+                                common::rate::format_duration(std::time::Duration::from_secs(now.saturating_sub(entry.finished_at))),
+                                verb,
+                                entry.file,
+                                common::transfer_state::format_bytes(entry.size),
+                                preposition,
+                                entry.peer(),
+                                entry.duration_secs)).wrap(true));
+                            ui.add(egui::Label::new(&entry.path).wrap(true));
+                            if let Some(error) = &entry.error {
+                                ui.colored_label(egui::Color32::RED, format!("Failed: {}", error));
+                            }
+                            ui.horizontal(|ui| {
+                                if ui.button("Open containing folder").clicked() {
+                                    open_folder(&entry.path);
+                                }
+                                // Received files may not be there anymore, nor be ours to send
+                                if entry.direction == Direction::Sent
+                                    && ui.add_enabled(self.resend_pending.is_none(), egui::Button::new("Resend to the same peer")).clicked()
+                                {
+                                    resend = Some(entry.clone());
+                                }
+                            });
+                            ui.separator();
+                        }
+                    });
+
+                    if let Some(entry) = resend {
+                        self.history_error = None;
+                        self.resend_pending = Some(entry.peer().to_string());
+                        let node = self.node.clone();
+                        let sender = self.resend_sender.clone();
+                        thread::spawn(move || {
+                            let peer = node.find_peer(entry.peer_query());
+                            let _ = sender.send((entry, peer));
+                            (node.notify)();
+                        });
+                    }
+                },
                 _ => {}
             }
         });
@@ -1,35 +1,44 @@
 use std::env;
 
-mod networking;
 mod gui;
-mod common;
+
+use filetransfer_core::common;
+
+const USAGE: &str = "Usage: FileTransfer-V2";
+
+fn parse_args(args: &[String]) -> Result<common::args::PortOptions, String> {
+    let mut ports = common::args::PortOptions::default();
+    let mut args = args.iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        if !common::args::parse_option(arg, &mut args, &mut ports)? {
+            return Err(format!("Unknown argument {}", arg));
+        }
+    }
+
+    Ok(ports)
+}
 
 fn main() {
+    let args: Vec<String> = env::args().collect();
+    let port_options = match parse_args(&args) {
+        Ok(ports) => ports,
+        Err(e) => {
+            eprintln!("{}\n{} {}", e, USAGE, common::args::OPTIONS_USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Read once the command line has set the config dir
+    let ports = port_options.apply(common::config::current().ports);
+
     let options = eframe::NativeOptions::default();
     match eframe::run_native(
         "FileTransfer V2",
         options,
-        Box::new(|_cc| Box::new(gui::app::MyApp::new())),
+        Box::new(move |_cc| Box::new(gui::app::MyApp::new(ports))),
     ) {
         Ok(_) => (),
         Err(e) => eprintln!("Application error: {}", e),
     }
-
-    /*let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        println!("Please specify 'server' or 'client' as a command line argument.");
-        return;
-    }
-
-    match args[1].as_str() {
-        "server" => {
-            networking::server::info_socket();
-        }
-        "client" => {
-            networking::client::info_socket();
-        }
-        _ => {
-            println!("Unknown argument '{}'. Please specify 'server' or 'client'.", args[1]);
-        }
-    }*/
 }